@@ -1,7 +1,14 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::Clap;
 
+use crate::downloader::DownloaderKind;
+use crate::export::ExportFormat;
+use crate::query::{DownloadState, FilmQuery, FilmSort};
+use crate::sanitize::{CharacterSet, Normalization, Sanitizer};
+use crate::template::{FilenameTemplate, OutputLayout};
+
 #[derive(Clap, Debug)]
 #[clap(author, about, version)]
 pub struct Opts {
@@ -11,6 +18,453 @@ pub struct Opts {
 
     #[clap(flatten)]
     pub jaeger_opts: JaegerOpts,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Clap, Debug)]
+pub enum Command {
+    /// Fetches film metadata and downloads all films that are not already downloaded
+    Run(RunOpts),
+    /// Fetches the film listing and the metadata of new films
+    Sync(SyncOpts),
+    /// Downloads all films that are not already downloaded
+    Download(DownloadOpts),
+    /// Lists the films stored in the database
+    List(ListOpts),
+    /// Shows everything stored about a film
+    Show(ShowOpts),
+    /// Searches the title, original title, director and description of films
+    Search(SearchOpts),
+    /// Exports the films stored in the database
+    Export(ExportOpts),
+    /// Imports films from a JSON or NDJSON export
+    Import(ImportOpts),
+    /// Shows the recorded changes to the details of a film
+    History(HistoryOpts),
+    /// Applies pending database migrations
+    Migrate(MigrateOpts),
+}
+
+#[derive(Clap, Debug)]
+pub struct RunOpts {
+    #[clap(flatten)]
+    pub sync_opts: SyncOpts,
+
+    #[clap(flatten)]
+    pub download_opts: DownloadOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct SyncOpts {
+    /// Sets the number of films whose details are fetched concurrently
+    #[clap(
+        long,
+        default_value = "1",
+        value_name = "N",
+        env = "OFFSTREAM_FETCH_CONCURRENCY"
+    )]
+    pub fetch_concurrency: usize,
+
+    /// Fetches the details of every listed film again, updating the stored details
+    #[clap(long, conflicts_with = "refresh-older-than")]
+    pub refresh: bool,
+
+    /// Fetches the details of listed films again if they were last fetched longer than this ago
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub refresh_older_than: Option<Duration>,
+
+    #[clap(flatten)]
+    pub client_opts: ClientOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct DownloadOpts {
+    /// Sets the number of films that are downloaded concurrently
+    #[clap(
+        short,
+        long,
+        default_value = "1",
+        value_name = "N",
+        env = "OFFSTREAM_JOBS"
+    )]
+    pub jobs: usize,
+
+    /// Sets the program to download films with
+    #[clap(long, arg_enum, default_value = "auto", env = "OFFSTREAM_DOWNLOADER")]
+    pub downloader: DownloaderKind,
+
+    /// Sets the path of the downloader binary instead of looking it up in PATH
+    #[clap(long, value_name = "FILE", env = "OFFSTREAM_DOWNLOADER_PATH")]
+    pub downloader_path: Option<PathBuf>,
+
+    /// Passes an extra argument to the downloader. Can be given multiple times
+    #[clap(
+        long = "downloader-arg",
+        value_name = "ARG",
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    pub downloader_args: Vec<String>,
+
+    /// Sets the url of the Vimeo player the native downloader requests videos from
+    #[clap(
+        long,
+        value_name = "URL",
+        default_value = "https://player.vimeo.com",
+        env = "OFFSTREAM_VIMEO_PLAYER_URL"
+    )]
+    pub vimeo_player_url: String,
+
+    #[clap(flatten)]
+    pub output_opts: OutputOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct OutputOpts {
+    /// Sets the directory films are downloaded to
+    #[clap(
+        long,
+        default_value = "films",
+        value_name = "DIR",
+        env = "OFFSTREAM_OUTPUT_DIR"
+    )]
+    pub output_dir: PathBuf,
+
+    /// Sets the path of downloaded films relative to the output directory, without the file
+    /// extension. The placeholders {id}, {title}, {original_title}, {director},
+    /// {production_year}, {festival_year}, {genre} and {country} are replaced with the details
+    /// of the film
+    #[clap(
+        long,
+        default_value = "{production_year}/{director} - {title} ({production_year})",
+        value_name = "TEMPLATE",
+        env = "OFFSTREAM_FILENAME_TEMPLATE"
+    )]
+    pub filename_template: FilenameTemplate,
+
+    /// Sets the file system whose rules filenames must follow
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "windows",
+        env = "OFFSTREAM_FILENAME_CHARSET"
+    )]
+    pub filename_charset: CharacterSet,
+
+    /// Sets the maximum length of each file and directory name
+    #[clap(
+        long,
+        default_value = "255",
+        value_name = "N",
+        env = "OFFSTREAM_MAX_FILENAME_LENGTH",
+        parse(try_from_str = parse_max_filename_length)
+    )]
+    pub max_filename_length: usize,
+
+    /// Sets the Unicode normalization form of filenames
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "nfc",
+        env = "OFFSTREAM_UNICODE_NORMALIZATION"
+    )]
+    pub unicode_normalization: Normalization,
+}
+
+impl OutputOpts {
+    /// Returns where and how downloaded films are stored.
+    pub fn layout(self) -> OutputLayout {
+        OutputLayout {
+            output_dir: self.output_dir,
+            template: self.filename_template,
+            sanitizer: Sanitizer {
+                character_set: self.filename_charset,
+                max_length: self.max_filename_length,
+                normalization: self.unicode_normalization,
+            },
+        }
+    }
+}
+
+#[derive(Clap, Debug)]
+pub struct ListOpts {
+    #[clap(flatten)]
+    pub filter_opts: FilterOpts,
+
+    /// Sorts the films by this field
+    #[clap(long, arg_enum, default_value = "id", value_name = "FIELD")]
+    pub sort: FilmSort,
+
+    /// Sorts the films in descending order
+    #[clap(long)]
+    pub desc: bool,
+
+    /// Lists at most this many films
+    #[clap(long, value_name = "N")]
+    pub limit: Option<u32>,
+
+    /// Skips this many films
+    #[clap(long, value_name = "N")]
+    pub offset: Option<u32>,
+}
+
+#[derive(Clap, Debug)]
+pub struct ExportOpts {
+    /// Sets the export format
+    #[clap(short, long, arg_enum, default_value = "json")]
+    pub format: ExportFormat,
+
+    /// Sets a comma-separated list of fields to export, instead of all fields
+    #[clap(long, value_name = "FIELDS")]
+    pub fields: Option<String>,
+
+    /// Writes the export to this file instead of stdout
+    #[clap(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub filter_opts: FilterOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct FilterOpts {
+    /// Only includes films with this production year
+    #[clap(long)]
+    pub year: Option<u64>,
+
+    /// Only includes films shown at the festival year with this title
+    #[clap(long, value_name = "TITLE")]
+    pub festival_year: Option<String>,
+
+    /// Only includes films with the genre with this identifier
+    #[clap(long, value_name = "IDENTIFIER")]
+    pub genre: Option<String>,
+
+    /// Only includes films from the country with this code
+    #[clap(long, value_name = "CODE")]
+    pub country: Option<String>,
+
+    /// Only includes films in the competition with this name
+    #[clap(long, value_name = "NAME")]
+    pub competition: Option<String>,
+
+    /// Only includes films with this download state
+    #[clap(long, arg_enum, value_name = "STATE")]
+    pub download_state: Option<DownloadState>,
+
+    /// Only includes films with a duration of at least this many minutes
+    #[clap(long, value_name = "MINUTES")]
+    pub min_duration: Option<u64>,
+
+    /// Only includes films with a duration of at most this many minutes
+    #[clap(long, value_name = "MINUTES")]
+    pub max_duration: Option<u64>,
+
+    /// Only includes films with this age restriction
+    #[clap(long, value_name = "AGE")]
+    pub age_restriction: Option<String>,
+
+    /// Only includes films that have been delisted
+    #[clap(long, conflicts_with = "listed")]
+    pub delisted: bool,
+
+    /// Only includes films that are still listed
+    #[clap(long)]
+    pub listed: bool,
+}
+
+impl FilterOpts {
+    /// Applies the filters to the given `query`.
+    pub fn apply(self, mut query: FilmQuery) -> FilmQuery {
+        if let Some(year) = self.year {
+            query = query.production_year(year);
+        }
+
+        if let Some(festival_year) = self.festival_year {
+            query = query.festival_year(festival_year);
+        }
+
+        if let Some(genre) = self.genre {
+            query = query.genre(genre);
+        }
+
+        if let Some(country) = self.country {
+            query = query.country(country);
+        }
+
+        if let Some(competition) = self.competition {
+            query = query.competition(competition);
+        }
+
+        if let Some(download_state) = self.download_state {
+            query = query.download_state(download_state);
+        }
+
+        if let Some(duration) = self.min_duration {
+            query = query.min_duration(duration);
+        }
+
+        if let Some(duration) = self.max_duration {
+            query = query.max_duration(duration);
+        }
+
+        if let Some(age_restriction) = self.age_restriction {
+            query = query.age_restriction(age_restriction);
+        }
+
+        if self.delisted || self.listed {
+            query = query.delisted(self.delisted);
+        }
+
+        query
+    }
+}
+
+#[derive(Clap, Debug)]
+pub struct ShowOpts {
+    /// The id of the film
+    pub film_id: u64,
+}
+
+#[derive(Clap, Debug)]
+pub struct SearchOpts {
+    /// The terms to search for
+    #[clap(required = true)]
+    pub query: Vec<String>,
+
+    /// Interprets the query as an SQLite FTS5 query instead of plain terms
+    #[clap(long)]
+    pub raw: bool,
+
+    /// Only includes films with the genre with this identifier
+    #[clap(long, value_name = "IDENTIFIER")]
+    pub genre: Option<String>,
+
+    /// Only includes films from the country with this code
+    #[clap(long, value_name = "CODE")]
+    pub country: Option<String>,
+
+    /// Only includes films with this production year
+    #[clap(long)]
+    pub year: Option<u64>,
+
+    /// Only includes films in the competition with this name
+    #[clap(long, value_name = "NAME")]
+    pub competition: Option<String>,
+
+    /// Sets the maximum number of results
+    #[clap(long, default_value = "20", value_name = "N")]
+    pub limit: u32,
+}
+
+#[derive(Clap, Debug)]
+pub struct ImportOpts {
+    /// The export to import, either a JSON array or one JSON record per line. Reads from stdin if
+    /// omitted
+    #[clap(value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// Overwrites stored films whose details differ from the imported ones, instead of skipping
+    /// them. Listings and downloads that are already stored are kept as they are
+    #[clap(long)]
+    pub overwrite: bool,
+
+    /// Reports what would be imported without saving anything
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct HistoryOpts {
+    /// The id of the film
+    pub film_id: u64,
+}
+
+#[derive(Clap, Debug)]
+pub struct MigrateOpts {
+    /// Prints which migrations have been applied instead of applying pending ones
+    #[clap(long)]
+    pub status: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct ClientOpts {
+    /// Sets the base URL of the offstream API
+    #[clap(
+        long = "api-base-url",
+        default_value = "https://api.offstream.dk",
+        value_name = "URL",
+        env = "OFFSTREAM_API_BASE_URL"
+    )]
+    pub base_url: String,
+
+    /// Sets the origin header sent along with API requests
+    #[clap(
+        long = "api-origin",
+        default_value = "https://offstream.dk",
+        value_name = "URL",
+        env = "OFFSTREAM_API_ORIGIN"
+    )]
+    pub origin: String,
+
+    /// Sets the maximum number of attempts for requests that fail for transient reasons
+    #[clap(
+        long = "retry-max-attempts",
+        default_value = "4",
+        value_name = "N",
+        env = "OFFSTREAM_RETRY_MAX_ATTEMPTS"
+    )]
+    pub retry_max_attempts: u32,
+
+    /// Sets the delay before the first retry, which doubles with each subsequent retry
+    #[clap(
+        long = "retry-base-delay",
+        default_value = "500ms",
+        value_name = "DURATION",
+        parse(try_from_str = parse_duration),
+        env = "OFFSTREAM_RETRY_BASE_DELAY"
+    )]
+    pub retry_base_delay: Duration,
+
+    /// Sets the maximum delay between two attempts
+    #[clap(
+        long = "retry-max-delay",
+        default_value = "30s",
+        value_name = "DURATION",
+        parse(try_from_str = parse_duration),
+        env = "OFFSTREAM_RETRY_MAX_DELAY"
+    )]
+    pub retry_max_delay: Duration,
+
+    /// Sets whether to randomize the delay between attempts
+    #[clap(
+        long = "retry-jitter",
+        parse(try_from_str),
+        default_value = "true",
+        env = "OFFSTREAM_RETRY_JITTER"
+    )]
+    pub retry_jitter: bool,
+
+    /// Sets the average number of API requests per second
+    #[clap(
+        long = "requests-per-second",
+        default_value = "1",
+        value_name = "N",
+        parse(try_from_str = parse_requests_per_second),
+        env = "OFFSTREAM_REQUESTS_PER_SECOND"
+    )]
+    pub requests_per_second: f64,
+
+    /// Sets the number of API requests that can be sent in a burst before being rate limited
+    #[clap(
+        long = "burst",
+        default_value = "5",
+        value_name = "N",
+        env = "OFFSTREAM_BURST"
+    )]
+    pub burst: u32,
 }
 
 #[derive(Clap, Debug)]
@@ -28,3 +482,46 @@ pub struct JaegerOpts {
     #[clap(long = "jaeger-service-name", default_value = env!("CARGO_PKG_NAME"), env = "JAEGER_SERVICE_NAME")]
     pub service_name: String,
 }
+
+/// Parses a duration consisting of a number followed by one of the units `ms`, `s`, `m`, `h` or
+/// `d`, e.g. `500ms` or `7d`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let unit_pos = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in duration `{}`", s))?;
+    let (value, unit) = s.split_at(unit_pos);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid number in duration `{}`", s))?;
+
+    let multiplier = match unit {
+        "ms" => return Ok(Duration::from_millis(value)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(format!("invalid unit `{}` in duration `{}`", unit, s)),
+    };
+    let secs = value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("duration `{}` is too large", s))?;
+
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parses a maximum filename length, which must leave room for at least one character.
+fn parse_max_filename_length(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(length) if length > 0 => Ok(length),
+        _ => Err(format!("`{}` is not a positive number", s)),
+    }
+}
+
+/// Parses a positive number of requests per second.
+fn parse_requests_per_second(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("`{}` is not a positive number", s)),
+    }
+}
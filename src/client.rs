@@ -1,14 +1,25 @@
-use std::{collections::HashMap, ops::Deref};
-
-use reqwest::redirect::Policy;
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use chrono::DateTime;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, redirect::Policy, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tracing::{instrument, trace};
+use tokio::time::sleep;
+use tracing::{debug, instrument, trace, warn};
 use urlencoding::decode as url_decode;
 
-use crate::{error::ErrorKind, Error};
+use crate::{error::ErrorKind, rate_limit::RateLimiter, Error};
 
-const API_BASE_URI: &str = "https://api.offstream.dk";
+/// The default base URL of the API.
+pub const DEFAULT_API_BASE_URL: &str = "https://api.offstream.dk";
+/// The default `origin` header sent along with API requests.
+pub const DEFAULT_ORIGIN: &str = "https://offstream.dk";
 
 /// Deserializes an instance of type `T` from a string of JSON text, while wrapping the error as
 /// [`Error::JsonDeserializationFailed`].
@@ -40,8 +51,193 @@ pub struct Client {
     http: reqwest::Client,
     /// XSRF token needed to talk to the API.
     ///
-    /// This should be set/updated by calling [`Client::update_xsrf_token`].
-    xsrf_token: Option<String>,
+    /// This is set/updated by calling [`Client::update_xsrf_token`], which happens automatically
+    /// when the API reports that the session has expired.
+    xsrf_token: RwLock<Option<String>>,
+    /// The base URL of the API, without a trailing slash.
+    base_url: String,
+    /// The `origin` header sent along with API requests.
+    origin: String,
+    /// The policy for retrying requests that failed for transient reasons.
+    retry_policy: RetryPolicy,
+    /// The rate limiter that every request has to pass through.
+    rate_limiter: RateLimiter,
+}
+
+/// Policy for retrying requests that failed for transient reasons, such as timeouts or a
+/// `502 Bad Gateway`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the initial request.
+    pub max_attempts: u32,
+    /// The delay before the first retry, which is doubled for each subsequent retry.
+    pub base_delay: Duration,
+    /// The upper bound for the delay between two attempts.
+    pub max_delay: Duration,
+    /// Whether to randomize the delays to avoid retrying in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait before retrying after the given failed `attempt`, starting at 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if self.jitter {
+            // Keep at least half of the delay so that the backoff remains exponential
+            let half = delay / 2;
+            let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+
+            half + Duration::from_millis(jitter_ms)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Returns true if a response with the given `status` is worth retrying.
+#[inline]
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Returns true if the request that failed with `err` is worth retrying, as opposed to errors
+/// that will fail the same way every time, such as an invalid URL or a malformed body.
+#[inline]
+fn is_retryable_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.status().is_some_and(is_retryable_status)
+}
+
+/// Returns the delay requested by the `Retry-After` header of `res`, if any.
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    parse_retry_after(res.headers().get(RETRY_AFTER)?.to_str().ok()?)
+}
+
+/// Parses the value of a `Retry-After` header, which is either a number of seconds or an HTTP
+/// date. Dates in the past mean that the request can be retried right away.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+
+    SystemTime::from(date)
+        .duration_since(SystemTime::now())
+        .ok()
+        .or(Some(Duration::from_secs(0)))
+}
+
+/// Builder for a [`Client`] with a non-default configuration.
+#[derive(Debug)]
+pub struct ClientBuilder {
+    base_url: String,
+    origin: String,
+    retry_policy: RetryPolicy,
+    requests_per_second: f64,
+    burst: u32,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            base_url: DEFAULT_API_BASE_URL.to_string(),
+            origin: DEFAULT_ORIGIN.to_string(),
+            retry_policy: RetryPolicy::default(),
+            requests_per_second: 1.0,
+            burst: 5,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Sets the base URL that request paths are appended to.
+    pub fn base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Sets the `origin` header sent along with API requests.
+    pub fn origin<S: Into<String>>(mut self, origin: S) -> Self {
+        self.origin = origin.into();
+        self
+    }
+
+    /// Sets the policy for retrying requests that failed for transient reasons.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets the average number of requests per second and the number of requests that may be sent
+    /// in a burst before being limited.
+    pub fn rate_limit(mut self, requests_per_second: f64, burst: u32) -> Self {
+        self.requests_per_second = requests_per_second;
+        self.burst = burst;
+        self
+    }
+
+    /// Returns a new client using this configuration.
+    ///
+    /// # Errors
+    ///
+    /// If the [`reqwest::ClientBuilder`] fails to finalize, [`Error::HttpClientFailed`] is
+    /// returned. If the configured number of requests per second is not a positive, finite
+    /// number, [`Error::InvalidRateLimit`] is returned.
+    pub fn build(self) -> Result<Client, Error> {
+        let rate_limiter = RateLimiter::new(self.requests_per_second, self.burst)?;
+        let http_client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .cookie_store(true)
+            .build()
+            .map_err(|err| Error::from(ErrorKind::HttpClientFailed(err)))?;
+
+        let client = Client {
+            http: http_client,
+            xsrf_token: RwLock::new(None),
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            origin: self.origin,
+            retry_policy: self.retry_policy,
+            rate_limiter,
+        };
+
+        Ok(client)
+    }
+}
+
+/// HTTP status code Laravel uses to signal that the session (and thus the XSRF token) expired.
+const STATUS_PAGE_EXPIRED: u16 = 419;
+
+/// Returns true if the given response `status` indicates that the XSRF token should be renewed.
+#[inline]
+fn is_xsrf_token_expired(status: StatusCode) -> bool {
+    status == StatusCode::UNAUTHORIZED || status.as_u16() == STATUS_PAGE_EXPIRED
 }
 
 /// The API response when loading film data
@@ -54,6 +250,24 @@ struct GetFilmResponseRaw {
     pub status: GetFilmResponseStatus,
 }
 
+/// The response from [`Client::get_films`]
+#[derive(Deserialize, Debug)]
+pub struct GetFilmsResponse {
+    /// The listed films, keyed by their id
+    pub data: HashMap<String, FilmSummary>,
+}
+
+/// A film as it appears in the film listing
+#[derive(Deserialize, Debug)]
+pub struct FilmSummary {
+    pub id: u64,
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub director: Option<String>,
+    pub production_year: Option<u64>,
+    pub duration: Option<u64>,
+}
+
 /// The response from [`Client::get_film`]
 #[derive(Deserialize, Debug)]
 pub struct GetFilmResponse {
@@ -139,30 +353,16 @@ pub struct GetFilmResponseStatus {
 }
 
 impl Client {
-    /// Returns a new client.
-    ///
-    /// # Errors
-    ///
-    /// If the [`reqwest::ClientBuilder`] fails to finalize, [`Error::HttpClientFailed`] is
-    /// returned.
-    pub fn new() -> Result<Client, Error> {
-        let http_client = reqwest::Client::builder()
-            .redirect(Policy::none())
-            .cookie_store(true)
-            .build()
-            .map_err(|err| Error::from(ErrorKind::HttpClientFailed(err)))?;
-
-        let client = Client {
-            http: http_client,
-            xsrf_token: None,
-        };
-
-        Ok(client)
+    /// Returns a new [`ClientBuilder`] for configuring the API location.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
     }
 
     /// Requests a new XSRF token from the API, returning `Ok(())` on success.
     #[instrument]
-    pub async fn update_xsrf_token(&mut self) -> Result<(), Error> {
+    pub async fn update_xsrf_token(&self) -> Result<(), Error> {
+        self.rate_limiter.acquire().await;
+
         let res = self
             .build_get("/csrf-cookie")
             .send()
@@ -175,9 +375,11 @@ impl Client {
             .find(|x| x.name() == "XSRF-TOKEN")
             .map(|x| x.value().to_owned())
         {
-            self.xsrf_token = Some(url_decode(&xsrf)?);
+            let xsrf_token = url_decode(&xsrf)?;
 
-            trace!(?self.xsrf_token, "Updated XSRF token");
+            trace!(?xsrf_token, "Updated XSRF token");
+
+            *self.xsrf_token.write().unwrap() = Some(xsrf_token);
         } else {
             return Err(Error::from(ErrorKind::InvalidXsrfToken));
         }
@@ -185,10 +387,99 @@ impl Client {
         Ok(())
     }
 
+    /// Renews the XSRF token, wrapping any error as [`ErrorKind::XsrfTokenRefreshFailed`].
+    async fn refresh_xsrf_token(&self) -> Result<(), Error> {
+        debug!("Refreshing XSRF token");
+
+        self.update_xsrf_token()
+            .await
+            .map_err(|err| Error::from(ErrorKind::XsrfTokenRefreshFailed(Box::new(err))))
+    }
+
+    /// Builds a request using `build` and sends it, transparently refreshing the XSRF token and
+    /// replaying the request once if the token is missing or the API reports it as expired.
+    ///
+    /// Requests that fail for transient reasons are retried according to the clients
+    /// [`RetryPolicy`], honouring any `Retry-After` header sent by the API. Every attempt passes
+    /// through the clients [`RateLimiter`], which slows down when the API responds with
+    /// `429 Too Many Requests`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::XsrfTokenRefreshFailed`] if the token could not be refreshed, and
+    /// [`ErrorKind::HttpRequestFailed`] if the request failed for a non-transient reason or ran
+    /// out of attempts.
+    async fn send<F>(&self, build: F) -> Result<reqwest::Response, Error>
+    where
+        F: Fn() -> Result<reqwest::RequestBuilder, Error>,
+    {
+        if self.xsrf_token.read().unwrap().is_none() {
+            self.refresh_xsrf_token().await?;
+        }
+
+        let max_attempts = self.retry_policy.max_attempts.max(1);
+        let mut xsrf_token_refreshed = false;
+        let mut attempt = 1;
+
+        loop {
+            self.rate_limiter.acquire().await;
+
+            let res = build()?.send().await;
+
+            match &res {
+                Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    self.rate_limiter.slow_down()
+                }
+                Ok(res) if res.status().is_success() => self.rate_limiter.recover(),
+                _ => {}
+            }
+
+            let delay = match res {
+                Ok(res) if is_xsrf_token_expired(res.status()) && !xsrf_token_refreshed => {
+                    debug!(http.status = res.status().as_u16(), "XSRF token expired");
+
+                    self.refresh_xsrf_token().await?;
+                    xsrf_token_refreshed = true;
+
+                    continue;
+                }
+                Ok(res) if is_retryable_status(res.status()) && attempt < max_attempts => {
+                    warn!(
+                        http.status = res.status().as_u16(),
+                        attempt, max_attempts, "Request failed with a transient error"
+                    );
+
+                    // A server asking for a longer wait than we're willing to do is not allowed
+                    // to stall the sync
+                    retry_after(&res)
+                        .map(|delay| delay.min(self.retry_policy.max_delay))
+                        .unwrap_or_else(|| self.retry_policy.backoff(attempt))
+                }
+                Ok(res) => return Ok(res.error_for_status()?),
+                Err(err) if is_retryable_error(&err) && attempt < max_attempts => {
+                    warn!(
+                        ?err,
+                        attempt, max_attempts, "Request failed with a transient error"
+                    );
+
+                    self.retry_policy.backoff(attempt)
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            debug!(?delay, "Waiting before retrying request");
+            sleep(delay).await;
+
+            attempt += 1;
+        }
+    }
+
     #[instrument]
     pub async fn get_film(&self, film_id: u64) -> Result<GetFilmResponse, Error> {
         let data = json_to_string(&json!({ "film_id": film_id }))?;
-        let response = self.post("/films/load")?.body(data).send().await?;
+        let response = self
+            .send(|| Ok(self.post("/films/load")?.body(data.clone())))
+            .await?;
         let raw_response: GetFilmResponseRaw = response.json().await?;
 
         if let Some(film_data) = raw_response.data.into_iter().next().map(|(_, value)| value) {
@@ -206,8 +497,8 @@ impl Client {
 
     /// Requests and returns a complete list of films.
     #[instrument]
-    pub async fn get_films(&self) -> Result<serde_json::Value, Error> {
-        let res = self.get("/films")?.send().await?;
+    pub async fn get_films(&self) -> Result<GetFilmsResponse, Error> {
+        let res = self.send(|| self.get("/films")).await?;
         let body = res.text().await?;
         let json = json_from_str(&body)?;
 
@@ -222,20 +513,30 @@ impl Client {
     /// Returns an error if [`Client::xsrf_token`] is `None`
     #[instrument(skip(path), fields(http.path = path))]
     pub fn get(&self, path: &str) -> Result<reqwest::RequestBuilder, Error> {
-        let xsrf_token = self
-            .xsrf_token
-            .as_ref()
-            .ok_or_else(|| Error::from(ErrorKind::XsrfTokenMissing))?;
+        let xsrf_token = self.xsrf_token()?;
         let req = self.build_get(path).header("x-xsrf-token", xsrf_token);
 
         Ok(req)
     }
 
+    /// Returns a copy of the current XSRF token.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::XsrfTokenMissing`] if no token has been set.
+    fn xsrf_token(&self) -> Result<String, Error> {
+        self.xsrf_token
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::from(ErrorKind::XsrfTokenMissing))
+    }
+
     #[inline]
     fn build_get(&self, path: &str) -> reqwest::RequestBuilder {
         self.http
-            .get(&format!("{}{}", API_BASE_URI, path))
-            .header("origin", "https://offstream.dk")
+            .get(format!("{}{}", self.base_url, path))
+            .header("origin", self.origin.as_str())
             .header("content-type", "application/json")
     }
 
@@ -245,18 +546,94 @@ impl Client {
     /// # Errors
     /// Returns an error if [`Client::xsrf_token`] is `None`
     pub fn post(&self, path: &str) -> Result<reqwest::RequestBuilder, Error> {
-        let xsrf_token = self
-            .xsrf_token
-            .as_ref()
-            .ok_or_else(|| Error::from(ErrorKind::XsrfTokenMissing))?;
+        let xsrf_token = self.xsrf_token()?;
 
         let req = self
             .http
-            .post(&format!("{}{}", API_BASE_URI, path))
-            .header("origin", "https://offstream.dk")
+            .post(format!("{}{}", self.base_url, path))
+            .header("origin", self.origin.as_str())
             .header("content-type", "application/json")
             .header("x-xsrf-token", xsrf_token);
 
         Ok(req)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            jitter,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let policy = policy(false);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.backoff(7), Duration::from_secs(30));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn backoff_jitter_keeps_at_least_half_the_delay() {
+        let jittered = policy(true);
+        let exact = policy(false);
+
+        for attempt in 1..10 {
+            let delay = jittered.backoff(attempt);
+            let max = exact.backoff(attempt);
+
+            assert!(delay >= max / 2, "{:?} < {:?} / 2", delay, max);
+            assert!(delay <= max, "{:?} > {:?}", delay, max);
+        }
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn parses_retry_after_dates() {
+        let future = (Utc::now() + chrono::Duration::seconds(120)).to_rfc2822();
+        let delay = parse_retry_after(&future).unwrap();
+
+        assert!(delay > Duration::from_secs(110) && delay <= Duration::from_secs(120));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_retry_after() {
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("-5"), None);
+        assert_eq!(parse_retry_after(""), None);
+    }
+
+    #[test]
+    fn build_rejects_invalid_rates() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = ClientBuilder::default()
+                .rate_limit(rate, 1)
+                .build()
+                .unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                ErrorKind::InvalidRateLimit(rate).to_string()
+            );
+        }
+    }
+}
@@ -1,69 +1,379 @@
-use std::{ops::Deref, path::Path};
+use std::{collections::HashMap, ops::Deref, path::Path};
 
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
 use tracing::{instrument, trace};
 
-use crate::client::{FilmCountry, FilmGenre, FilmYear, GetFilmResponseData, GetFilmResponseStatus};
-use crate::Error;
+use crate::client::{
+    FilmCountry, FilmGenre, FilmSummary, FilmYear, GetFilmResponseData, GetFilmResponseStatus,
+};
+use crate::{migrations, query::FilmQuery, Error};
 
 #[derive(Debug)]
 pub struct Database(Connection);
 
 #[derive(Debug)]
 pub struct FilmStatus {
-    pub film_id: u64,
-    pub status: Option<String>,
-    pub vimeo_id: String,
+    pub vimeo_id: Option<String>,
     pub greeting_vimeo_id: Option<String>,
 }
 
+/// What a download of a film contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadKind {
+    /// The film itself.
+    Film,
+    /// A greeting from the producer, shown before the film.
+    Greeting,
+}
+
+impl DownloadKind {
+    /// Returns the name the kind is stored as.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DownloadKind::Film => "film",
+            DownloadKind::Greeting => "greeting",
+        }
+    }
+}
+
+impl ToSql for DownloadKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for DownloadKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "film" => Ok(DownloadKind::Film),
+            "greeting" => Ok(DownloadKind::Greeting),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Why a download attempt failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureCategory {
+    /// The downloader could not be started.
+    Launch,
+    /// The video doesn't exist or isn't accessible.
+    Unavailable,
+    /// A request was answered with an error status.
+    Http,
+    /// A request could not be sent or timed out.
+    Network,
+    /// The video was downloaded, but could not be merged or converted.
+    Postprocessing,
+    /// The video could not be written to disk.
+    Io,
+    /// A response could not be understood.
+    InvalidResponse,
+    Other,
+}
+
+impl FailureCategory {
+    /// Returns the name the category is stored as.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureCategory::Launch => "launch",
+            FailureCategory::Unavailable => "unavailable",
+            FailureCategory::Http => "http",
+            FailureCategory::Network => "network",
+            FailureCategory::Postprocessing => "postprocessing",
+            FailureCategory::Io => "io",
+            FailureCategory::InvalidResponse => "invalid_response",
+            FailureCategory::Other => "other",
+        }
+    }
+}
+
+impl ToSql for FailureCategory {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for FailureCategory {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "launch" => Ok(FailureCategory::Launch),
+            "unavailable" => Ok(FailureCategory::Unavailable),
+            "http" => Ok(FailureCategory::Http),
+            "network" => Ok(FailureCategory::Network),
+            "postprocessing" => Ok(FailureCategory::Postprocessing),
+            "io" => Ok(FailureCategory::Io),
+            "invalid_response" => Ok(FailureCategory::InvalidResponse),
+            "other" => Ok(FailureCategory::Other),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// The details of a failed download attempt.
+#[derive(Debug, Clone)]
+pub struct DownloadAttemptFailure {
+    pub category: FailureCategory,
+    /// The exit code of the downloader program, if it exited on its own.
+    pub exit_status: Option<i32>,
+    /// The last lines the downloader program wrote to stderr.
+    pub stderr_tail: Option<String>,
+    pub error: String,
+}
+
+/// A single run of a downloader.
+#[derive(Debug)]
+pub struct DownloadAttempt {
+    pub kind: DownloadKind,
+    /// The name of the downloader, e.g. `yt-dlp`.
+    pub downloader: String,
+    pub started_at: DateTime<Utc>,
+    /// When the attempt ended, or `None` if it was interrupted.
+    pub finished_at: Option<DateTime<Utc>>,
+    pub failure: Option<DownloadAttemptFailure>,
+}
+
 #[derive(Debug)]
 pub struct MissingFilmDownload {
     pub id: u64,
-    pub title: String,
-    pub original_title: Option<String>,
-    pub director: String,
-    pub production_year: u64,
+    pub kind: DownloadKind,
+    pub title: Option<String>,
+    pub director: Option<String>,
+    pub production_year: Option<u64>,
+}
+
+impl MissingFilmDownload {
+    /// Returns the names of the fields used for naming downloads that the film is missing.
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        let mut fields = vec![];
+
+        if self.title.is_none() {
+            fields.push("title");
+        }
+
+        if self.director.is_none() {
+            fields.push("director");
+        }
+
+        if self.production_year.is_none() {
+            fields.push("production_year");
+        }
+
+        fields
+    }
 }
 
 #[derive(Debug)]
 pub struct FilmDownload {
-    pub id: u64,
-    pub film_id: u64,
     pub started_at: DateTime<Utc>,
     pub finished_at: Option<DateTime<Utc>>,
     pub path: String,
 }
 
-/// Opens and initializes a database
-pub fn open<P: AsRef<Path>>(path: P) -> Result<Database, rusqlite::Error> {
+/// A thumbnail of a film and the file it has been downloaded to, if it has been.
+#[derive(Debug, Clone)]
+pub struct FilmThumbnail {
+    pub resolution: String,
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub size: Option<u64>,
+    /// The hex-encoded SHA-256 hash of the downloaded image.
+    pub sha256: Option<String>,
+}
+
+/// Everything stored about a film.
+#[derive(Debug)]
+pub struct Film {
+    pub id: u64,
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub director: Option<String>,
+    pub production_year: Option<u64>,
+    pub duration: Option<u64>,
+    pub description: Option<String>,
+    pub age_restriction: Option<String>,
+    /// When the details of the film were last fetched, if known.
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub status: Option<String>,
+    pub vimeo_id: Option<String>,
+    pub greeting_vimeo_id: Option<String>,
+    /// The festival year the film was shown at.
+    pub year: Option<FilmYear>,
+    /// When the film first appeared in the film listing.
+    pub listed_at: Option<DateTime<Utc>>,
+    /// When the film was last seen in the film listing.
+    pub last_seen_at: Option<DateTime<Utc>>,
+    /// When the film disappeared from the film listing, if it has.
+    pub delisted_at: Option<DateTime<Utc>>,
+    /// Thumbnails in the format `(resolution, url)`.
+    pub thumbnails: Vec<(String, String)>,
+    /// The thumbnails that have been downloaded, along with their files.
+    pub thumbnail_files: Vec<FilmThumbnail>,
+    pub genres: Vec<FilmGenre>,
+    pub countries: Vec<FilmCountry>,
+    pub competitions: Vec<String>,
+    pub download: Option<FilmDownload>,
+    pub greeting_download: Option<FilmDownload>,
+}
+
+/// Selects the columns read by [`film_from_row`].
+const FILM_SELECT: &str = "
+    SELECT
+        f.id, f.title, f.original_title, f.director, f.production_year, f.duration,
+        f.description, f.age_restriction, f.fetched_at,
+        s.status, s.vimeo_id, s.greeting_vimeo_id,
+        y.id, y.title, CAST(y.product_id AS INTEGER),
+        l.listed_at, l.last_seen_at, l.delisted_at,
+        dl.id, dl.started_at, dl.finished_at, dl.path,
+        gdl.id, gdl.started_at, gdl.finished_at, gdl.path
+    FROM films AS f
+    LEFT JOIN film_status AS s ON s.film_id = f.id
+    LEFT JOIN film_years AS y ON y.film_id = f.id
+    LEFT JOIN film_listings AS l ON l.film_id = f.id
+    LEFT JOIN film_downloads AS dl ON dl.film_id = f.id AND dl.kind = 'film'
+    LEFT JOIN film_downloads AS gdl ON gdl.film_id = f.id AND gdl.kind = 'greeting'
+";
+
+/// Returns a film without its thumbnails, genres, countries and competitions from a row selected
+/// by [`FILM_SELECT`].
+fn film_from_row(row: &rusqlite::Row) -> Result<Film, rusqlite::Error> {
+    let id = row.get(0)?;
+    let year = match row.get::<_, Option<u64>>(12)? {
+        Some(year_id) => Some(FilmYear {
+            id: year_id,
+            title: row.get(13)?,
+            product_id: row.get(14)?,
+        }),
+        None => None,
+    };
+    // The film and greeting downloads are selected as four columns each, starting at `start`
+    let download = |start: usize| -> Result<Option<FilmDownload>, rusqlite::Error> {
+        match row.get::<_, Option<u64>>(start)? {
+            Some(_) => Ok(Some(FilmDownload {
+                started_at: row.get(start + 1)?,
+                finished_at: row.get(start + 2)?,
+                path: row.get(start + 3)?,
+            })),
+            None => Ok(None),
+        }
+    };
+
+    Ok(Film {
+        id,
+        title: row.get(1)?,
+        original_title: row.get(2)?,
+        director: row.get(3)?,
+        production_year: row.get(4)?,
+        duration: row.get(5)?,
+        description: row.get(6)?,
+        age_restriction: row.get(7)?,
+        fetched_at: row.get(8)?,
+        status: row.get(9)?,
+        vimeo_id: row.get(10)?,
+        greeting_vimeo_id: row.get(11)?,
+        year,
+        listed_at: row.get(15)?,
+        last_seen_at: row.get(16)?,
+        delisted_at: row.get(17)?,
+        thumbnails: vec![],
+        thumbnail_files: vec![],
+        genres: vec![],
+        countries: vec![],
+        competitions: vec![],
+        download: download(18)?,
+        greeting_download: download(22)?,
+    })
+}
+
+/// A full-text search for films, with optional filters.
+#[derive(Debug, Default)]
+pub struct FilmSearch {
+    /// The FTS5 query matched against the title, original title, director and description.
+    pub query: String,
+    /// Only match films with the genre with this identifier.
+    pub genre: Option<String>,
+    /// Only match films from the country with this code.
+    pub country: Option<String>,
+    /// Only match films with this production year.
+    pub year: Option<u64>,
+    /// Only match films in the competition with this name.
+    pub competition: Option<String>,
+    /// The maximum number of results.
+    pub limit: u32,
+}
+
+/// A film matching a [`FilmSearch`].
+#[derive(Debug)]
+pub struct FilmSearchResult {
+    pub film_id: u64,
+    pub title: Option<String>,
+    pub director: Option<String>,
+    pub production_year: Option<u64>,
+    /// An excerpt of the best matching field, with the matched terms in `[brackets]`.
+    pub snippet: String,
+    /// The relevance of the match, where lower is better.
+    pub rank: f64,
+}
+
+/// Returns an FTS5 query matching every whitespace-separated term in `text` literally.
+pub fn fts_literal_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A change to a field of a film, as recorded when the film was updated.
+#[derive(Debug)]
+pub struct FilmHistoryEntry {
+    /// The name of the changed field, e.g. `title` or `vimeo_id`.
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Opens a database and applies any pending migrations
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Database, Error> {
     let db = Database::open(path)?;
 
-    trace!("Setting up initial SQL");
-    db.execute_batch(include_str!("init.sql"))?;
+    trace!("Applying pending migrations");
+    migrations::migrate(&db)?;
 
     Ok(db)
 }
 
 impl Database {
+    /// Opens a database without applying any migrations.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Database, rusqlite::Error> {
         let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
 
         Ok(Database(conn))
     }
 
-    /// Inserts a new film into the database.
+    /// Inserts a new film into the database, or updates it if it already exists.
     #[instrument(err, skip(self))]
-    pub fn create_film(&self, id: u64, film_data: &GetFilmResponseData) -> Result<(), Error> {
-        trace!("Creating film entry");
+    pub fn upsert_film(&self, id: u64, film_data: &GetFilmResponseData) -> Result<(), Error> {
+        trace!("Upserting film entry");
 
         self.execute(
             "
             INSERT INTO films
-                (id, title, original_title, director, production_year, duration, description, age_restriction)
+                (id, title, original_title, director, production_year, duration, description, age_restriction, fetched_at)
             VALUES
-                (?, ?, ?, ?, ?, ?, ?, ?)
+                (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                title = excluded.title,
+                original_title = excluded.original_title,
+                director = excluded.director,
+                production_year = excluded.production_year,
+                duration = excluded.duration,
+                description = excluded.description,
+                age_restriction = excluded.age_restriction,
+                fetched_at = excluded.fetched_at
             ",
             params!(
                 id,
@@ -73,22 +383,83 @@ impl Database {
                 film_data.production_year,
                 film_data.duration,
                 film_data.description,
-                film_data.age_restriction
+                film_data.age_restriction,
+                Utc::now()
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    /// Inserts or updates the listing of a film, as returned by the film list, marking it as
+    /// seen at `seen_at` and no longer delisted.
+    #[instrument(err, skip(self, film), fields(film_id = film.id))]
+    pub fn upsert_film_listing(
+        &self,
+        film: &FilmSummary,
+        seen_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        trace!("Upserting film listing");
+
+        self.execute(
+            "
+            INSERT INTO film_listings
+                (film_id, title, original_title, director, production_year, duration, last_seen_at)
+            VALUES
+                (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (film_id) DO UPDATE SET
+                title = excluded.title,
+                original_title = excluded.original_title,
+                director = excluded.director,
+                production_year = excluded.production_year,
+                duration = excluded.duration,
+                last_seen_at = excluded.last_seen_at,
+                delisted_at = NULL
+            ",
+            params!(
+                film.id,
+                film.title,
+                film.original_title,
+                film.director,
+                film.production_year,
+                film.duration,
+                seen_at
             ),
         )?;
 
         Ok(())
     }
 
-    /// Inserts a new thumbnail into the database.
+    /// Marks every listed film that was not seen at `seen_at` as delisted, returning the number
+    /// of films that were newly delisted.
     #[instrument(err, skip(self))]
-    pub fn create_film_thumbnail(
+    pub fn mark_delisted_films(&self, seen_at: DateTime<Utc>) -> Result<usize, Error> {
+        trace!("Marking films absent from the listing as delisted");
+
+        let num_delisted = self.execute(
+            "
+            UPDATE film_listings
+            SET delisted_at = ?1
+            WHERE delisted_at IS NULL AND last_seen_at IS NOT ?1
+            ",
+            [seen_at],
+        )?;
+
+        Ok(num_delisted)
+    }
+
+    /// Inserts a new thumbnail into the database, or updates its url if the film already has a
+    /// thumbnail with the given `resolution`.
+    ///
+    /// A thumbnail whose url changes is no longer considered downloaded.
+    #[instrument(err, skip(self))]
+    pub fn upsert_film_thumbnail(
         &self,
         film_id: u64,
         resolution: &str,
         url: Option<&str>,
     ) -> Result<(), Error> {
-        trace!("Creating thumbnail entry");
+        trace!("Upserting thumbnail entry");
 
         self.execute(
             "
@@ -96,6 +467,12 @@ impl Database {
                 (film_id, resolution, url)
             VALUES
                 (?, ?, ?)
+            ON CONFLICT (film_id, resolution) DO UPDATE SET
+                url = excluded.url,
+                path = CASE WHEN url IS excluded.url THEN path END,
+                size = CASE WHEN url IS excluded.url THEN size END,
+                sha256 = CASE WHEN url IS excluded.url THEN sha256 END,
+                downloaded_at = CASE WHEN url IS excluded.url THEN downloaded_at END
             ",
             params!(film_id, resolution, url),
         )?;
@@ -103,6 +480,59 @@ impl Database {
         Ok(())
     }
 
+    /// Replaces the thumbnails of a film with the given `thumbnails`, keyed by resolution.
+    #[instrument(err, skip(self))]
+    pub fn sync_film_thumbnails(
+        &self,
+        film_id: u64,
+        thumbnails: &HashMap<String, String>,
+    ) -> Result<(), Error> {
+        for (resolution, _url) in self.get_film_thumbnails(film_id)? {
+            if !thumbnails.contains_key(&resolution) {
+                trace!(resolution = resolution.as_str(), "Removing stale thumbnail");
+
+                self.execute(
+                    "DELETE FROM film_thumbnails WHERE film_id = ? AND resolution = ?",
+                    params!(film_id, resolution),
+                )?;
+            }
+        }
+
+        for (resolution, url) in thumbnails {
+            self.upsert_film_thumbnail(film_id, resolution, Some(url.as_str()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Records that the thumbnail of a film with the given `resolution` has been downloaded to
+    /// `path`.
+    #[instrument(err, skip(self))]
+    pub fn update_film_thumbnail_file(
+        &self,
+        film_id: u64,
+        resolution: &str,
+        path: &str,
+        size: u64,
+        sha256: &str,
+    ) -> Result<(), Error> {
+        trace!("Updating thumbnail file");
+
+        self.execute(
+            "
+            UPDATE film_thumbnails SET
+                path = ?,
+                size = ?,
+                sha256 = ?,
+                downloaded_at = ?
+            WHERE film_id = ? AND resolution = ?
+            ",
+            params!(path, size, sha256, Utc::now(), film_id, resolution),
+        )?;
+
+        Ok(())
+    }
+
     /// Returns a list of thumbnails in the tuple format `(resolution, url)` if any.
     #[instrument(err, skip(self))]
     pub fn get_film_thumbnails(&self, film_id: u64) -> Result<Vec<(String, String)>, Error> {
@@ -123,23 +553,67 @@ impl Database {
         Ok(thumbs)
     }
 
+    /// Returns the thumbnails of a film along with the files they have been downloaded to.
+    #[instrument(err, skip(self))]
+    pub fn get_film_thumbnail_files(&self, film_id: u64) -> Result<Vec<FilmThumbnail>, Error> {
+        trace!("Querying for film thumbnail files");
+
+        let mut stmt = self.prepare(
+            "SELECT resolution, url, path, size, sha256
+            FROM film_thumbnails
+            WHERE film_id = ?
+            ORDER BY resolution",
+        )?;
+
+        let res = stmt
+            .query_map([film_id], |row| {
+                Ok(FilmThumbnail {
+                    resolution: row.get(0)?,
+                    url: row.get(1)?,
+                    path: row.get(2)?,
+                    size: row.get(3)?,
+                    sha256: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(res)
+    }
+
+    /// Returns the ids of films with thumbnails that haven't been downloaded yet.
+    #[instrument(err, skip(self))]
+    pub fn get_films_with_missing_thumbnails(&self) -> Result<Vec<u64>, Error> {
+        trace!("Querying for films with missing thumbnails");
+
+        let mut stmt = self.prepare(
+            "SELECT DISTINCT film_id
+            FROM film_thumbnails
+            WHERE path IS NULL AND url IS NOT NULL
+            ORDER BY film_id",
+        )?;
+
+        let res = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(res)
+    }
+
     /// Returns a film status for a given `film_id`.
     #[instrument(err, skip(self))]
     pub fn get_film_status(&self, film_id: u64) -> Result<FilmStatus, Error> {
         trace!("Querying for film status");
 
         let mut stmt = self.prepare(
-            "SELECT film_id, status, vimeo_id, greeting_vimeo_id
+            "SELECT vimeo_id, greeting_vimeo_id
                 FROM film_status
-                WHERE film_id = ? AND vimeo_id IS NOT NULL",
+                WHERE film_id = ?",
         )?;
 
         let res = stmt.query_row([film_id], |row| {
             Ok(FilmStatus {
-                film_id: row.get(0)?,
-                status: row.get(1)?,
-                vimeo_id: row.get(2)?,
-                greeting_vimeo_id: row.get(3)?,
+                vimeo_id: row.get(0)?,
+                greeting_vimeo_id: row.get(1)?,
             })
         })?;
 
@@ -202,14 +676,14 @@ impl Database {
         Ok(id)
     }
 
-    /// Creates an association between a film and a genre.
+    /// Creates an association between a film and a genre, if not already present.
     #[instrument(err, skip(self))]
     pub fn create_film_genre(&self, film_id: u64, genre_id: u64) -> Result<(), Error> {
         trace!("Creating genre and film association");
 
         self.execute(
             "
-            INSERT INTO film_genres
+            INSERT OR IGNORE INTO film_genres
                 (film_id, genre_id)
             VALUES
                 (?, ?)
@@ -234,14 +708,14 @@ impl Database {
         Ok(id)
     }
 
-    /// Creates an association between a film and a country.
+    /// Creates an association between a film and a country, if not already present.
     #[instrument(err, skip(self))]
     pub fn create_film_country(&self, film_id: u64, country_id: u64) -> Result<(), Error> {
         trace!("Creating country and film association");
 
         self.execute(
             "
-            INSERT INTO film_countries
+            INSERT OR IGNORE INTO film_countries
                 (film_id, country_id)
             VALUES
                 (?, ?)
@@ -252,10 +726,10 @@ impl Database {
         Ok(())
     }
 
-    /// Creates an association between a film and a country.
+    /// Creates or updates the festival year of a film.
     #[instrument(err, skip(self))]
-    pub fn create_film_year(&self, film_id: u64, year: &FilmYear) -> Result<(), Error> {
-        trace!("Creating film year",);
+    pub fn upsert_film_year(&self, film_id: u64, year: &FilmYear) -> Result<(), Error> {
+        trace!("Upserting film year");
 
         self.execute(
             "
@@ -263,6 +737,10 @@ impl Database {
                 (id, film_id, title, product_id)
             VALUES
                 (?, ?, ?, ?)
+            ON CONFLICT (film_id) DO UPDATE SET
+                id = excluded.id,
+                title = excluded.title,
+                product_id = excluded.product_id
             ",
             params!(year.id, film_id, year.title, year.product_id),
         )?;
@@ -270,14 +748,14 @@ impl Database {
         Ok(())
     }
 
-    /// Creates a film status for a given `film_id`.
+    /// Creates or updates the film status for a given `film_id`.
     #[instrument(err, skip(self))]
-    pub fn create_film_status(
+    pub fn upsert_film_status(
         &self,
         film_id: u64,
         status: &GetFilmResponseStatus,
     ) -> Result<(), Error> {
-        trace!("Creating film status");
+        trace!("Upserting film status");
 
         self.execute(
             "
@@ -285,6 +763,10 @@ impl Database {
                 (film_id, status, vimeo_id, greeting_vimeo_id)
             VALUES
                 (?, ?, ?, ?)
+            ON CONFLICT (film_id) DO UPDATE SET
+                status = excluded.status,
+                vimeo_id = excluded.vimeo_id,
+                greeting_vimeo_id = excluded.greeting_vimeo_id
             ",
             params!(
                 film_id,
@@ -297,14 +779,14 @@ impl Database {
         Ok(())
     }
 
-    /// Creates a film competition for a given `film_id`.
+    /// Creates a film competition for a given `film_id`, if not already present.
     #[instrument(err, skip(self))]
     pub fn create_film_competition(&self, film_id: u64, competition: &str) -> Result<(), Error> {
         trace!("Creating film competition");
 
         self.execute(
             "
-            INSERT INTO film_competitions
+            INSERT OR IGNORE INTO film_competitions
                 (film_id, name)
             VALUES
                 (?, ?)
@@ -315,6 +797,399 @@ impl Database {
         Ok(())
     }
 
+    /// Removes all genre, country and competition associations of a film, so that they can be
+    /// recreated from freshly fetched film data.
+    #[instrument(err, skip(self))]
+    pub fn clear_film_associations(&self, film_id: u64) -> Result<(), Error> {
+        trace!("Clearing film associations");
+
+        self.execute("DELETE FROM film_genres WHERE film_id = ?", [film_id])?;
+        self.execute("DELETE FROM film_countries WHERE film_id = ?", [film_id])?;
+        self.execute("DELETE FROM film_competitions WHERE film_id = ?", [film_id])?;
+
+        Ok(())
+    }
+
+    /// Returns the ids of the given `film_ids` that are either not in the database, or were last
+    /// fetched before `fetched_before`, if given.
+    #[instrument(err, skip(self, film_ids))]
+    pub fn get_film_ids_to_fetch(
+        &self,
+        film_ids: &[u64],
+        fetched_before: Option<DateTime<Utc>>,
+    ) -> Result<Vec<u64>, Error> {
+        let mut stmt = self.prepare("SELECT fetched_at FROM films WHERE id = ?")?;
+        let mut res = vec![];
+
+        for &film_id in film_ids {
+            let fetched_at: Option<Option<DateTime<Utc>>> =
+                stmt.query_row([film_id], |row| row.get(0)).optional()?;
+
+            let should_fetch = match (fetched_at, fetched_before) {
+                // The film is not in the database
+                (None, _) => true,
+                // The film is in the database and we're not refreshing
+                (Some(_), None) => false,
+                // The film was stored before we kept track of when it was fetched
+                (Some(None), Some(_)) => true,
+                (Some(Some(fetched_at)), Some(cutoff)) => fetched_at < cutoff,
+            };
+
+            if should_fetch {
+                res.push(film_id);
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Returns everything stored about the film with the given `film_id`, if present.
+    #[instrument(err, skip(self))]
+    pub fn get_film(&self, film_id: u64) -> Result<Option<Film>, Error> {
+        trace!("Querying for film");
+
+        let film = self
+            .query_row(
+                &format!("{} WHERE f.id = ?", FILM_SELECT),
+                [film_id],
+                film_from_row,
+            )
+            .optional()?;
+
+        match film {
+            Some(mut film) => {
+                self.load_film_relations(&mut film)?;
+
+                Ok(Some(film))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Stores everything in `film`, replacing any previously stored details, thumbnails and
+    /// associations of the film.
+    ///
+    /// Unlike [`Database::upsert_film`], this keeps the timestamps of `film`. The film listing,
+    /// each kind of download and thumbnail files are only stored if the film doesn't already have
+    /// them - they're never overwritten, so that importing a film can't undo a local download.
+    /// The caller is expected to wrap this in a transaction.
+    #[instrument(err, skip(self, film), fields(film_id = film.id))]
+    pub fn save_film(&self, film: &Film) -> Result<(), Error> {
+        trace!("Saving film");
+
+        self.execute(
+            "
+            INSERT INTO films
+                (id, title, original_title, director, production_year, duration, description, age_restriction, fetched_at)
+            VALUES
+                (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                title = excluded.title,
+                original_title = excluded.original_title,
+                director = excluded.director,
+                production_year = excluded.production_year,
+                duration = excluded.duration,
+                description = excluded.description,
+                age_restriction = excluded.age_restriction,
+                fetched_at = excluded.fetched_at
+            ",
+            params!(
+                film.id,
+                film.title,
+                film.original_title,
+                film.director,
+                film.production_year,
+                film.duration,
+                film.description,
+                film.age_restriction,
+                film.fetched_at
+            ),
+        )?;
+
+        if film.status.is_some() || film.vimeo_id.is_some() || film.greeting_vimeo_id.is_some() {
+            self.execute(
+                "
+                INSERT INTO film_status
+                    (film_id, status, vimeo_id, greeting_vimeo_id)
+                VALUES
+                    (?, ?, ?, ?)
+                ON CONFLICT (film_id) DO UPDATE SET
+                    status = excluded.status,
+                    vimeo_id = excluded.vimeo_id,
+                    greeting_vimeo_id = excluded.greeting_vimeo_id
+                ",
+                params!(film.id, film.status, film.vimeo_id, film.greeting_vimeo_id),
+            )?;
+        }
+
+        if let Some(year) = &film.year {
+            self.upsert_film_year(film.id, year)?;
+        }
+
+        self.sync_film_thumbnails(film.id, &film.thumbnails.iter().cloned().collect())?;
+
+        // Like downloads, files are only recorded for thumbnails that haven't been downloaded,
+        // and only if they were downloaded from the thumbnail's current url
+        for thumbnail in &film.thumbnail_files {
+            if let (Some(path), Some(size), Some(sha256)) =
+                (&thumbnail.path, thumbnail.size, &thumbnail.sha256)
+            {
+                self.execute(
+                    "
+                    UPDATE film_thumbnails SET
+                        path = ?,
+                        size = ?,
+                        sha256 = ?,
+                        downloaded_at = ?
+                    WHERE film_id = ? AND resolution = ? AND url IS ? AND path IS NULL
+                    ",
+                    params!(
+                        path,
+                        size,
+                        sha256,
+                        Utc::now(),
+                        film.id,
+                        thumbnail.resolution,
+                        thumbnail.url
+                    ),
+                )?;
+            }
+        }
+        self.clear_film_associations(film.id)?;
+
+        self.sync_genres(&film.genres)?;
+
+        for genre in &film.genres {
+            if let Some(genre_id) = self.get_genre(genre.id())? {
+                self.create_film_genre(film.id, genre_id)?;
+            }
+        }
+
+        self.sync_countries(&film.countries)?;
+
+        for country in &film.countries {
+            if let Some(country_id) = self.get_country(country.code())? {
+                self.create_film_country(film.id, country_id)?;
+            }
+        }
+
+        for competition in &film.competitions {
+            self.create_film_competition(film.id, competition)?;
+        }
+
+        if let Some(listed_at) = film.listed_at {
+            self.execute(
+                "
+                INSERT INTO film_listings
+                    (film_id, title, original_title, director, production_year, duration, listed_at, last_seen_at, delisted_at)
+                VALUES
+                    (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (film_id) DO NOTHING
+                ",
+                params!(
+                    film.id,
+                    film.title,
+                    film.original_title,
+                    film.director,
+                    film.production_year,
+                    film.duration,
+                    listed_at,
+                    film.last_seen_at,
+                    film.delisted_at
+                ),
+            )?;
+        }
+
+        let downloads = [
+            (DownloadKind::Film, &film.download),
+            (DownloadKind::Greeting, &film.greeting_download),
+        ];
+
+        for (kind, download) in downloads {
+            if let Some(download) = download {
+                self.execute(
+                    "
+                    INSERT INTO film_downloads
+                        (film_id, kind, started_at, finished_at, path)
+                    VALUES
+                        (?, ?, ?, ?, ?)
+                    ON CONFLICT (film_id, kind) DO NOTHING
+                    ",
+                    params!(
+                        film.id,
+                        kind,
+                        download.started_at,
+                        download.finished_at,
+                        download.path
+                    ),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns everything stored about the films matching `query`.
+    #[instrument(err, skip(self))]
+    pub fn query_films(&self, query: &FilmQuery) -> Result<Vec<Film>, Error> {
+        trace!("Querying for films");
+
+        let (clauses, params) = query.to_sql();
+        let mut stmt = self.prepare(&format!("{}{}", FILM_SELECT, clauses))?;
+        let mut films = stmt
+            .query_map(
+                rusqlite::params_from_iter(params.iter().map(Box::as_ref)),
+                film_from_row,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for film in &mut films {
+            self.load_film_relations(film)?;
+        }
+
+        Ok(films)
+    }
+
+    /// Loads the thumbnails, genres, countries and competitions of `film`.
+    fn load_film_relations(&self, film: &mut Film) -> Result<(), Error> {
+        film.thumbnails = self.get_film_thumbnails(film.id)?;
+        film.thumbnail_files = self
+            .get_film_thumbnail_files(film.id)?
+            .into_iter()
+            .filter(|thumbnail| thumbnail.path.is_some())
+            .collect();
+
+        film.genres = self
+            .prepare(
+                "SELECT g.identifier, g.title
+                FROM film_genres AS fg
+                INNER JOIN genres AS g ON g.id = fg.genre_id
+                WHERE fg.film_id = ?
+                ORDER BY g.title",
+            )?
+            .query_map([film.id], |row| {
+                Ok(FilmGenre {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        film.countries = self
+            .prepare(
+                "SELECT c.title, c.code
+                FROM film_countries AS fc
+                INNER JOIN countries AS c ON c.id = fc.country_id
+                WHERE fc.film_id = ?
+                ORDER BY c.title",
+            )?
+            .query_map([film.id], |row| {
+                Ok(FilmCountry {
+                    title: row.get(0)?,
+                    code: row.get(1)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        film.competitions = self
+            .prepare("SELECT name FROM film_competitions WHERE film_id = ? ORDER BY name")?
+            .query_map([film.id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(())
+    }
+
+    /// Returns the films matching `search`, best match first.
+    ///
+    /// Matches in the title weigh the most, followed by the original title and director, and
+    /// lastly the description.
+    #[instrument(err, skip(self))]
+    pub fn search_films(&self, search: &FilmSearch) -> Result<Vec<FilmSearchResult>, Error> {
+        trace!("Searching for films");
+
+        let mut stmt = self.prepare(
+            "
+            SELECT
+                f.id, f.title, f.director, f.production_year,
+                snippet(films_fts, -1, '[', ']', '…', 16),
+                bm25(films_fts, 10.0, 5.0, 5.0, 1.0) AS rank
+            FROM films_fts
+            INNER JOIN films AS f ON f.id = films_fts.rowid
+            WHERE films_fts MATCH ?1
+            AND (?2 IS NULL OR EXISTS (
+                SELECT 1 FROM film_genres AS fg
+                INNER JOIN genres AS g ON g.id = fg.genre_id
+                WHERE fg.film_id = f.id AND g.identifier = ?2
+            ))
+            AND (?3 IS NULL OR EXISTS (
+                SELECT 1 FROM film_countries AS fc
+                INNER JOIN countries AS c ON c.id = fc.country_id
+                WHERE fc.film_id = f.id AND c.code = ?3 COLLATE NOCASE
+            ))
+            AND (?4 IS NULL OR f.production_year = ?4)
+            AND (?5 IS NULL OR EXISTS (
+                SELECT 1 FROM film_competitions AS fc
+                WHERE fc.film_id = f.id AND fc.name = ?5 COLLATE NOCASE
+            ))
+            ORDER BY rank
+            LIMIT ?6
+            ",
+        )?;
+
+        let res = stmt
+            .query_map(
+                params!(
+                    search.query,
+                    search.genre,
+                    search.country,
+                    search.year,
+                    search.competition,
+                    search.limit
+                ),
+                |row| {
+                    Ok(FilmSearchResult {
+                        film_id: row.get(0)?,
+                        title: row.get(1)?,
+                        director: row.get(2)?,
+                        production_year: row.get(3)?,
+                        snippet: row.get(4)?,
+                        rank: row.get(5)?,
+                    })
+                },
+            )?
+            .collect::<Result<_, _>>()?;
+
+        Ok(res)
+    }
+
+    /// Returns the recorded field changes of a film, oldest first.
+    ///
+    /// Changes to the `films` and `film_status` tables are recorded by triggers.
+    #[instrument(err, skip(self))]
+    pub fn get_film_history(&self, film_id: u64) -> Result<Vec<FilmHistoryEntry>, Error> {
+        trace!("Querying for film history");
+
+        let mut stmt = self.prepare(
+            "SELECT field, CAST(old_value AS TEXT), CAST(new_value AS TEXT), changed_at
+                FROM film_history
+                WHERE film_id = ?
+                ORDER BY changed_at, id",
+        )?;
+
+        let res = stmt
+            .query_map([film_id], |row| {
+                Ok(FilmHistoryEntry {
+                    field: row.get(0)?,
+                    old_value: row.get(1)?,
+                    new_value: row.get(2)?,
+                    changed_at: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(res)
+    }
+
     /// Inserts any missing `genres` into the database if not present.
     pub fn sync_genres(&self, genres: &[FilmGenre]) -> Result<(), Error> {
         let mut stmt = self.prepare("SELECT identifier FROM genres WHERE identifier = ?")?;
@@ -367,34 +1242,41 @@ impl Database {
     }
 
     pub fn get_missing_downloads(&self) -> Result<Vec<MissingFilmDownload>, Error> {
+        // Films without a vimeo id can't be downloaded until they get one, and only films with
+        // a greeting have a greeting download
         let mut stmt = self.prepare(
-            "SELECT f.id, f.title, f.original_title, f.director, f.production_year
+            "SELECT f.id, k.kind, f.title, f.director, f.production_year
             FROM films AS f
+            CROSS JOIN (SELECT 'film' AS kind UNION ALL SELECT 'greeting') AS k
+            LEFT JOIN film_status AS s
+            ON f.id = s.film_id
             LEFT JOIN film_downloads AS dl
-            ON f.id = dl.film_id
-            WHERE dl.id IS NULL OR dl.finished_at IS NULL",
+            ON f.id = dl.film_id AND k.kind = dl.kind
+            WHERE (dl.id IS NULL OR dl.finished_at IS NULL)
+            AND IFNULL(CASE k.kind WHEN 'film' THEN s.vimeo_id ELSE s.greeting_vimeo_id END, '') != ''
+            ORDER BY f.id, k.kind",
         )?;
 
         let res = stmt
             .query_map([], |row| {
                 Ok(MissingFilmDownload {
                     id: row.get(0)?,
-                    title: row.get(1)?,
-                    original_title: row.get(2)?,
+                    kind: row.get(1)?,
+                    title: row.get(2)?,
                     director: row.get(3)?,
                     production_year: row.get(4)?,
                 })
             })?
-            .filter_map(Result::ok)
-            .collect();
+            .collect::<Result<_, _>>()?;
 
         Ok(res)
     }
 
-    /// Inserts or updates a film download with the given `completed` info.
+    /// Inserts or updates a film download of the given `kind` with the given `completed` info.
     pub fn upsert_film_download(
         &self,
         film_id: u64,
+        kind: DownloadKind,
         completed: bool,
         path: Option<&str>,
     ) -> Result<(), Error> {
@@ -402,16 +1284,99 @@ impl Database {
 
         self.execute(
             "
-            REPLACE INTO film_downloads
-            (film_id, finished_at, path)
+            INSERT INTO film_downloads
+            (film_id, kind, finished_at, path)
             VALUES
-            (?, ?, ?)
+            (?, ?, ?, ?)
+            ON CONFLICT (film_id, kind) DO UPDATE SET
+                finished_at = excluded.finished_at,
+                path = excluded.path
             ",
-            params!(film_id, finished_at, path),
+            params!(film_id, kind, finished_at, path),
+        )?;
+
+        Ok(())
+    }
+
+    /// Records the start of a download attempt of the given `kind` of a film, returning the id of
+    /// the attempt.
+    pub fn start_download_attempt(
+        &self,
+        film_id: u64,
+        kind: DownloadKind,
+        downloader: &str,
+    ) -> Result<i64, Error> {
+        self.execute(
+            "INSERT INTO download_attempts (film_id, kind, downloader, started_at)
+                VALUES (?, ?, ?, ?)",
+            params!(film_id, kind, downloader, Utc::now()),
+        )?;
+
+        Ok(self.last_insert_rowid())
+    }
+
+    /// Records the end of the download attempt with the given `attempt_id`, along with why it
+    /// failed if it did.
+    pub fn finish_download_attempt(
+        &self,
+        attempt_id: i64,
+        failure: Option<&DownloadAttemptFailure>,
+    ) -> Result<(), Error> {
+        self.execute(
+            "UPDATE download_attempts
+                SET finished_at = ?, exit_status = ?, stderr_tail = ?, error_category = ?, error = ?
+                WHERE id = ?",
+            params!(
+                Utc::now(),
+                failure.and_then(|failure| failure.exit_status),
+                failure.and_then(|failure| failure.stderr_tail.as_deref()),
+                failure.map(|failure| failure.category),
+                failure.map(|failure| failure.error.as_str()),
+                attempt_id
+            ),
         )?;
 
         Ok(())
     }
+
+    /// Returns the download attempts of a film, oldest first.
+    #[instrument(err, skip(self))]
+    pub fn get_download_attempts(&self, film_id: u64) -> Result<Vec<DownloadAttempt>, Error> {
+        trace!("Querying for download attempts");
+
+        let mut stmt = self.prepare(
+            "SELECT kind, downloader, started_at, finished_at, exit_status, stderr_tail,
+                    error_category, error
+                FROM download_attempts
+                WHERE film_id = ?
+                ORDER BY started_at, id",
+        )?;
+
+        let res = stmt
+            .query_map([film_id], |row| {
+                let category: Option<FailureCategory> = row.get(6)?;
+                let failure = match category {
+                    Some(category) => Some(DownloadAttemptFailure {
+                        category,
+                        exit_status: row.get(4)?,
+                        stderr_tail: row.get(5)?,
+                        error: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                    }),
+                    None => None,
+                };
+
+                Ok(DownloadAttempt {
+                    kind: row.get(0)?,
+                    downloader: row.get(1)?,
+                    started_at: row.get(2)?,
+                    finished_at: row.get(3)?,
+                    failure,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(res)
+    }
 }
 
 impl Deref for Database {
@@ -421,3 +1386,187 @@ impl Deref for Database {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        export::ExportRecord,
+        query::{DownloadState, FilmSort},
+    };
+
+    /// Returns an in-memory database with all migrations applied.
+    fn database() -> Database {
+        let db = Database(Connection::open_in_memory().unwrap());
+        db.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+        migrations::migrate(&db).unwrap();
+
+        db
+    }
+
+    #[test]
+    fn queries_films() {
+        let db = database();
+
+        db.execute_batch(
+            "
+            INSERT INTO films (id, title, director, production_year, duration)
+            VALUES (1, 'Alpha', 'Someone', 2020, 90),
+                   (2, 'beta', 'Someone Else', 2020, 30),
+                   (3, 'Gamma', 'Someone', 2021, 100);
+
+            INSERT INTO countries (id, title, code) VALUES (1, 'Denmark', 'DK');
+            INSERT INTO film_countries (film_id, country_id) VALUES (1, 1), (2, 1), (3, 1);
+
+            INSERT INTO film_listings (film_id, title) VALUES (1, 'Alpha'), (2, 'beta'), (3, 'Gamma');
+            UPDATE film_listings SET delisted_at = CURRENT_TIMESTAMP WHERE film_id = 3;
+            ",
+        )
+        .unwrap();
+        db.upsert_film_download(2, DownloadKind::Film, true, Some("beta.mp4"))
+            .unwrap();
+
+        let ids = |query: FilmQuery| -> Vec<u64> {
+            db.query_films(&query)
+                .unwrap()
+                .iter()
+                .map(|film| film.id)
+                .collect()
+        };
+
+        assert_eq!(
+            ids(FilmQuery::new()
+                .country("dk")
+                .delisted(false)
+                .sort(FilmSort::Title, true)),
+            vec![2, 1]
+        );
+        assert_eq!(
+            ids(FilmQuery::new()
+                .production_year(2020)
+                .download_state(DownloadState::Missing)
+                .min_duration(60)),
+            vec![1]
+        );
+        assert_eq!(ids(FilmQuery::new().limit(1).offset(2)), vec![3]);
+    }
+
+    #[test]
+    fn saves_vimeo_ids_without_a_status() {
+        let db = database();
+        let record: ExportRecord = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "title": "Alpha",
+            "vimeo_id": "123",
+            "greeting_vimeo_id": "456",
+        }))
+        .unwrap();
+
+        db.save_film(&Film::from(record)).unwrap();
+
+        let status = db.get_film_status(1).unwrap();
+        assert_eq!(status.vimeo_id.as_deref(), Some("123"));
+        assert_eq!(status.greeting_vimeo_id.as_deref(), Some("456"));
+    }
+
+    #[test]
+    fn saves_thumbnail_files_of_current_urls() {
+        let db = database();
+        let record: ExportRecord = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "thumbnails": {
+                "640": "https://example.com/640.jpg",
+                "1280": "https://example.com/1280.jpg",
+            },
+            "thumbnail_files": {
+                "640": {
+                    "url": "https://example.com/640.jpg",
+                    "path": "films/alpha.thumbnails/640.jpg",
+                    "size": 1234,
+                    "sha256": "abcd",
+                },
+                "1280": {
+                    "url": "https://example.com/old-1280.jpg",
+                    "path": "films/alpha.thumbnails/1280.jpg",
+                    "size": 5678,
+                    "sha256": "ef01",
+                },
+            },
+        }))
+        .unwrap();
+
+        db.save_film(&Film::from(record.clone())).unwrap();
+
+        // The file of the 1280 thumbnail was downloaded from a url it no longer has
+        let film = db.get_film(1).unwrap().unwrap();
+        let exported = ExportRecord::from(&film);
+
+        assert_eq!(exported.thumbnails, record.thumbnails);
+        assert_eq!(exported.thumbnail_files.len(), 1);
+        assert_eq!(
+            exported.thumbnail_files["640"],
+            record.thumbnail_files["640"]
+        );
+    }
+
+    #[test]
+    fn skips_downloads_without_a_vimeo_id() {
+        let db = database();
+
+        db.execute_batch(
+            "
+            INSERT INTO films (id, title) VALUES (1, 'Alpha'), (2, 'Beta'), (3, 'Gamma'), (4, 'Delta');
+
+            INSERT INTO film_status (film_id, status, vimeo_id, greeting_vimeo_id)
+            VALUES (1, 'ok', '123', '456'), (2, 'ok', NULL, '789'), (3, 'ok', '', NULL);
+            ",
+        )
+        .unwrap();
+
+        let missing: Vec<(u64, DownloadKind)> = db
+            .get_missing_downloads()
+            .unwrap()
+            .into_iter()
+            .map(|download| (download.id, download.kind))
+            .collect();
+
+        assert_eq!(
+            missing,
+            vec![
+                (1, DownloadKind::Film),
+                (1, DownloadKind::Greeting),
+                (2, DownloadKind::Greeting),
+            ]
+        );
+    }
+
+    #[test]
+    fn completing_a_download_keeps_its_start_time() {
+        let db = database();
+
+        db.execute_batch(
+            "INSERT INTO films (id, title, director, production_year)
+             VALUES (1, 'Alpha', 'Someone', 2020)",
+        )
+        .unwrap();
+        db.upsert_film_download(1, DownloadKind::Film, false, Some("alpha.mp4"))
+            .unwrap();
+        db.execute_batch("UPDATE film_downloads SET started_at = '2021-01-01 00:00:00'")
+            .unwrap();
+        db.upsert_film_download(1, DownloadKind::Film, true, Some("alpha (1).mp4"))
+            .unwrap();
+
+        let (started_at, finished_at, path): (String, Option<DateTime<Utc>>, String) = db
+            .query_row(
+                "SELECT started_at, finished_at, path FROM film_downloads
+                 WHERE film_id = 1 AND kind = 'film'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+
+        assert_eq!(started_at, "2021-01-01 00:00:00");
+        assert!(finished_at.is_some());
+        assert_eq!(path, "alpha (1).mp4");
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use clap::ArgEnum;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tracing::{debug, info, instrument, trace, warn};
+
+use crate::database::{DownloadAttemptFailure, FailureCategory};
+use crate::error::{Error, ErrorKind};
+use crate::progress::{self, DownloadProgress, Progress};
+use crate::vimeo::VimeoDownloader;
+
+/// The referer sent along with video requests, without which embedded videos can't be played.
+pub const REFERER: &str = "https://offstream.dk/";
+/// The number of lines written to stderr that are kept for when a program fails.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Which program to download videos with.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloaderKind {
+    /// Uses yt-dlp if it is installed, then youtube-dl, and the native downloader otherwise.
+    Auto,
+    YoutubeDl,
+    YtDlp,
+    /// Downloads from the Vimeo player directly, without any external programs.
+    Native,
+}
+
+/// Downloads videos to files.
+pub trait Downloader: fmt::Debug + Send + Sync {
+    /// Returns the name of the downloader.
+    fn name(&self) -> &'static str;
+
+    /// Downloads the Vimeo video with the given `vimeo_id` to `output_path`, reporting its
+    /// progress to `progress`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the download fails.
+    fn download<'a>(
+        &'a self,
+        vimeo_id: &'a str,
+        output_path: &'a Path,
+        progress: &'a DownloadProgress,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Returns the url of the embedded player of the Vimeo video with the given `vimeo_id`.
+fn player_url(vimeo_id: &str) -> String {
+    format!("https://player.vimeo.com/video/{}?app_id=122963", vimeo_id)
+}
+
+/// A youtube-dl compatible program.
+#[derive(Debug)]
+struct Program {
+    binary: PathBuf,
+    /// Arguments given before the default arguments, so that they can't override the output
+    /// path or url.
+    extra_args: Vec<String>,
+}
+
+impl Program {
+    /// Runs the program with the `extra_args` followed by `args`, reporting the progress it
+    /// prints to `progress`.
+    async fn run(
+        &self,
+        name: &str,
+        args: &[&str],
+        progress: &DownloadProgress,
+    ) -> Result<(), Error> {
+        let mut cmd = Command::new(&self.binary);
+        cmd.args(&self.extra_args)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        debug!(binary = ?self.binary, extra_args = ?self.extra_args, ?args, "Running {}", name);
+
+        let mut child = cmd.spawn().map_err(|err| {
+            Error::from(ErrorKind::YouTubeDlError(ProgramFailure {
+                category: FailureCategory::Launch,
+                exit_status: None,
+                stderr_tail: None,
+                message: format!("Could not create process: {}", err),
+            }))
+        })?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let mut last_error = None;
+        let mut stderr_tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+
+        let read_stdout = for_each_line(stdout, |line| {
+            if let Some(line_progress) = Progress::parse(line) {
+                progress.update(&line_progress);
+                return;
+            }
+
+            // Video and audio are downloaded in passes that are each reported from 0 to 100%
+            if let Some(passes) = progress::parse_num_passes(line) {
+                progress.expect_passes(passes);
+            } else if progress::is_destination(line) {
+                progress.start_pass();
+            }
+
+            debug!(output = line, "{} output", name);
+        });
+        let read_stderr = for_each_line(stderr, |line| {
+            if stderr_tail.len() == STDERR_TAIL_LINES {
+                stderr_tail.pop_front();
+            }
+
+            stderr_tail.push_back(line.to_string());
+
+            if let Some(message) = line.strip_prefix("ERROR:") {
+                last_error = Some(message.trim().to_string());
+            } else if let Some(message) = line.strip_prefix("WARNING:") {
+                warn!(message = message.trim(), "{} warning", name);
+            } else {
+                debug!(output = line, "{} output", name);
+            }
+        });
+
+        let (status, _, _) = tokio::join!(child.wait(), read_stdout, read_stderr);
+        let status = status?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            let stderr_tail = Vec::from(stderr_tail).join("\n");
+            let message = match &last_error {
+                Some(error) => format!("{} failed: {}: {}", name, status, error),
+                None => format!("{} failed: {}", name, status),
+            };
+
+            Err(ErrorKind::YouTubeDlError(ProgramFailure {
+                category: last_error
+                    .as_deref()
+                    .map_or(FailureCategory::Other, categorize_program_error),
+                exit_status: status.code(),
+                stderr_tail: Some(stderr_tail).filter(|tail| !tail.is_empty()),
+                message,
+            })
+            .into())
+        }
+    }
+}
+
+/// A downloader program that could not be run or exited unsuccessfully.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct ProgramFailure {
+    category: FailureCategory,
+    exit_status: Option<i32>,
+    stderr_tail: Option<String>,
+    message: String,
+}
+
+/// Guesses why a program failed from the last error message it printed, e.g.
+/// `Unable to download webpage: HTTP Error 404: Not Found`.
+fn categorize_program_error(error: &str) -> FailureCategory {
+    let error = error.to_lowercase();
+    let contains_any = |needles: &[&str]| needles.iter().any(|needle| error.contains(needle));
+
+    if contains_any(&[
+        "http error 403",
+        "http error 404",
+        "http error 410",
+        "unavailable",
+        "private",
+        "does not exist",
+    ]) {
+        FailureCategory::Unavailable
+    } else if error.contains("http error") {
+        FailureCategory::Http
+    } else if contains_any(&[
+        "timed out",
+        "connection",
+        "name resolution",
+        "network",
+        "urlopen error",
+    ]) {
+        FailureCategory::Network
+    } else if contains_any(&["ffmpeg", "ffprobe", "postprocessing", "merging"]) {
+        FailureCategory::Postprocessing
+    } else if contains_any(&["no space left", "permission denied", "unable to open"]) {
+        FailureCategory::Io
+    } else {
+        FailureCategory::Other
+    }
+}
+
+/// Returns the details of a failed download to record with its download attempt.
+///
+/// Failures of downloader programs keep their exit status and stderr, while other errors, like
+/// those of the native downloader, are categorized by what caused them.
+pub fn attempt_failure(err: &Error) -> DownloadAttemptFailure {
+    let mut category = FailureCategory::Other;
+    let mut source: Option<&(dyn StdError + 'static)> = Some(err);
+
+    while let Some(cause) = source {
+        if let Some(failure) = cause.downcast_ref::<ProgramFailure>() {
+            return DownloadAttemptFailure {
+                category: failure.category,
+                exit_status: failure.exit_status,
+                stderr_tail: failure.stderr_tail.clone(),
+                error: failure.message.clone(),
+            };
+        } else if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            category = match err.status() {
+                Some(status) if matches!(status.as_u16(), 403 | 404 | 410) => {
+                    FailureCategory::Unavailable
+                }
+                Some(_) => FailureCategory::Http,
+                None if err.is_decode() => FailureCategory::InvalidResponse,
+                None => FailureCategory::Network,
+            };
+            break;
+        } else if cause.is::<std::io::Error>() {
+            category = FailureCategory::Io;
+            break;
+        } else if cause.is::<serde_path_to_error::Error<serde_json::Error>>() {
+            category = FailureCategory::InvalidResponse;
+            break;
+        }
+
+        source = cause.source();
+    }
+
+    DownloadAttemptFailure {
+        category,
+        exit_status: None,
+        stderr_tail: None,
+        error: err.to_string(),
+    }
+}
+
+/// Calls `f` with each non-empty line read from `reader`, treating carriage returns as line
+/// breaks since progress is redrawn on the same line.
+async fn for_each_line<R, F>(reader: R, mut f: F)
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(&str),
+{
+    let mut lines = BufReader::new(reader).split(b'\n');
+
+    loop {
+        match lines.next_segment().await {
+            Ok(Some(line)) => String::from_utf8_lossy(&line)
+                .split('\r')
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .for_each(&mut f),
+            Ok(None) => break,
+            Err(err) => {
+                trace!(%err, "Could not read program output");
+                break;
+            }
+        }
+    }
+}
+
+/// Downloads videos using [youtube-dl](https://youtube-dl.org/).
+#[derive(Debug)]
+pub struct YoutubeDl(Program);
+
+impl Downloader for YoutubeDl {
+    fn name(&self) -> &'static str {
+        "youtube-dl"
+    }
+
+    fn download<'a>(
+        &'a self,
+        vimeo_id: &'a str,
+        output_path: &'a Path,
+        progress: &'a DownloadProgress,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let url = player_url(vimeo_id);
+            let output_path = output_path.to_string_lossy();
+            let args = [
+                "--newline",
+                "--referer",
+                REFERER,
+                "-f",
+                "bestvideo+bestaudio",
+                "--merge-output-format",
+                "mp4",
+                "-o",
+                output_path.as_ref(),
+                &url,
+            ];
+
+            // The video and the audio are always downloaded separately
+            progress.expect_passes(2);
+
+            self.0.run(self.name(), &args, progress).await
+        }
+        .boxed()
+    }
+}
+
+/// Downloads videos using [yt-dlp](https://github.com/yt-dlp/yt-dlp), the maintained fork of
+/// youtube-dl.
+#[derive(Debug)]
+pub struct YtDlp(Program);
+
+impl Downloader for YtDlp {
+    fn name(&self) -> &'static str {
+        "yt-dlp"
+    }
+
+    fn download<'a>(
+        &'a self,
+        vimeo_id: &'a str,
+        output_path: &'a Path,
+        progress: &'a DownloadProgress,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let url = player_url(vimeo_id);
+            let output_path = output_path.to_string_lossy();
+            let args = [
+                "--newline",
+                "--referer",
+                REFERER,
+                "-f",
+                "bv*+ba/b",
+                "--merge-output-format",
+                "mp4",
+                "-o",
+                output_path.as_ref(),
+                &url,
+            ];
+
+            // Usually the video and the audio are downloaded separately, but yt-dlp reports the
+            // formats it picked before downloading them
+            progress.expect_passes(2);
+
+            self.0.run(self.name(), &args, progress).await
+        }
+        .boxed()
+    }
+}
+
+/// Returns the version printed by `binary --version`, or `None` if it can't be run.
+#[instrument]
+async fn program_version(binary: &Path) -> Option<String> {
+    let output = Command::new(binary).arg("--version").output().await;
+
+    match output {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+            trace!(version = version.as_str(), "Found program");
+
+            Some(version)
+        }
+        Ok(output) => {
+            trace!(status = %output.status, "Program exited unsuccessfully");
+
+            None
+        }
+        Err(err) => {
+            trace!(%err, "Could not run program");
+
+            None
+        }
+    }
+}
+
+/// Finds the program to download videos with, returning a downloader that runs it with the given
+/// `extra_args`.
+///
+/// If `binary` is given, it's used instead of looking the program up in `PATH`. With
+/// [`DownloaderKind::Auto`], yt-dlp is preferred over youtube-dl, and the native downloader is
+/// used if neither is installed. The native downloader requests videos from the player at
+/// `player_url`.
+///
+/// # Errors
+///
+/// Returns [`ErrorKind::DownloaderNotFound`] if none of the candidate programs can be run.
+pub async fn detect(
+    kind: DownloaderKind,
+    binary: Option<&Path>,
+    extra_args: Vec<String>,
+    player_url: &str,
+) -> Result<Box<dyn Downloader>, Error> {
+    let candidates: &[DownloaderKind] = match kind {
+        // A given binary is assumed to be yt-dlp if it's named like it
+        DownloaderKind::Auto if binary.is_some() => {
+            let name = binary
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+
+            if name.contains("yt-dlp") {
+                &[DownloaderKind::YtDlp]
+            } else {
+                &[DownloaderKind::YoutubeDl]
+            }
+        }
+        DownloaderKind::Auto => &[
+            DownloaderKind::YtDlp,
+            DownloaderKind::YoutubeDl,
+            DownloaderKind::Native,
+        ],
+        DownloaderKind::YoutubeDl => &[DownloaderKind::YoutubeDl],
+        DownloaderKind::YtDlp => &[DownloaderKind::YtDlp],
+        DownloaderKind::Native => &[DownloaderKind::Native],
+    };
+
+    let mut tried = vec![];
+
+    for candidate in candidates {
+        if *candidate == DownloaderKind::Native {
+            info!(downloader = "native", player_url, "Using downloader");
+
+            return Ok(Box::new(VimeoDownloader::new(player_url)?));
+        }
+
+        let binary = match binary {
+            Some(binary) => binary.to_path_buf(),
+            None => PathBuf::from(match candidate {
+                DownloaderKind::YtDlp => "yt-dlp",
+                _ => "youtube-dl",
+            }),
+        };
+
+        if let Some(version) = program_version(&binary).await {
+            let program = Program {
+                binary,
+                extra_args: extra_args.clone(),
+            };
+            let downloader: Box<dyn Downloader> = match candidate {
+                DownloaderKind::YtDlp => Box::new(YtDlp(program)),
+                _ => Box::new(YoutubeDl(program)),
+            };
+
+            info!(
+                downloader = downloader.name(),
+                version = version.as_str(),
+                "Using downloader"
+            );
+
+            return Ok(downloader);
+        }
+
+        tried.push(binary.to_string_lossy().into_owned());
+    }
+
+    Err(ErrorKind::DownloaderNotFound(tried.join(", ")).into())
+}
@@ -31,6 +31,8 @@ pub enum ErrorKind {
     XsrfTokenMissing,
     #[error("Could not request a new XSRF token")]
     XsrfTokenRequestFailed(#[source] reqwest::Error),
+    #[error("Could not refresh the expired XSRF token")]
+    XsrfTokenRefreshFailed(#[source] Box<Error>),
     #[error("The API response did not include an XSRF token")]
     InvalidXsrfToken,
     #[error("URL decoding error")]
@@ -41,12 +43,40 @@ pub enum ErrorKind {
     JsonSerializationFailed(#[source] serde_json::Error),
     #[error("API error: {0}")]
     ApiError(String),
+    #[error("Invalid rate limit of {0} requests per second")]
+    InvalidRateLimit(f64),
     #[error("Could not build reqwest HTTP client")]
     HttpClientFailed(#[source] reqwest::Error),
     #[error("HTTP request failed: {0}")]
     HttpRequestFailed(#[from] reqwest::Error),
     #[error("Database error: {0}")]
     DatabaseError(#[from] rusqlite::Error),
-    #[error("Youtube-DL error: {0}")]
-    YouTubeDlError(String),
+    #[error("Invalid import record on line {0}")]
+    InvalidImportRecord(
+        usize,
+        #[source] serde_path_to_error::Error<serde_json::Error>,
+    ),
+    #[error("Invalid import record at index {0}")]
+    InvalidImportArrayRecord(
+        usize,
+        #[source] serde_path_to_error::Error<serde_json::Error>,
+    ),
+    #[error("Unknown export field `{0}`")]
+    UnknownExportField(String),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Could not find a film with id {0}")]
+    FilmNotFound(u64),
+    #[error("Database schema version {0} is newer than the latest known version {1}")]
+    UnknownSchemaVersion(u32, u32),
+    #[error("Invalid filename template `{0}`: {1}")]
+    InvalidFilenameTemplate(String, String),
+    #[error("Could not find a downloader, tried {0}")]
+    DownloaderNotFound(String),
+    #[error("Youtube-DL error")]
+    YouTubeDlError(#[source] crate::downloader::ProgramFailure),
+    #[error("Vimeo error: {0}")]
+    VimeoError(String),
+    #[error("Invalid MP4: {0}")]
+    InvalidMp4(String),
 }
@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::client::{FilmCountry, FilmGenre, FilmYear};
+use crate::database::{Film, FilmDownload, FilmThumbnail};
+use crate::error::{Error, ErrorKind};
+
+/// The names of all fields of an [`ExportRecord`], in the order they are exported.
+pub const FIELDS: &[&str] = &[
+    "id",
+    "title",
+    "original_title",
+    "director",
+    "production_year",
+    "duration",
+    "description",
+    "age_restriction",
+    "fetched_at",
+    "status",
+    "vimeo_id",
+    "greeting_vimeo_id",
+    "festival_year_id",
+    "festival_year",
+    "festival_year_product_id",
+    "genres",
+    "countries",
+    "competitions",
+    "thumbnails",
+    "thumbnail_files",
+    "listed_at",
+    "last_seen_at",
+    "delisted_at",
+    "download_path",
+    "download_started_at",
+    "download_finished_at",
+    "greeting_download_path",
+    "greeting_download_started_at",
+    "greeting_download_finished_at",
+];
+
+/// The separator between multiple values in a single CSV field.
+const CSV_LIST_SEPARATOR: &str = "; ";
+
+/// The format of an export.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A single JSON array of records.
+    Json,
+    /// One JSON record per line.
+    Ndjson,
+    /// Comma-separated values with a header row, where lists are joined by `; `.
+    Csv,
+}
+
+/// A genre as it appears in an export.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportGenre {
+    pub identifier: String,
+    pub title: String,
+}
+
+/// A country as it appears in an export.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportCountry {
+    pub code: String,
+    pub title: String,
+}
+
+/// A downloaded thumbnail as it appears in an export.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportThumbnailFile {
+    /// The url the thumbnail was downloaded from.
+    pub url: Option<String>,
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A film and everything associated with it, denormalized into a single record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportRecord {
+    pub id: u64,
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub director: Option<String>,
+    pub production_year: Option<u64>,
+    pub duration: Option<u64>,
+    pub description: Option<String>,
+    pub age_restriction: Option<String>,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub status: Option<String>,
+    pub vimeo_id: Option<String>,
+    pub greeting_vimeo_id: Option<String>,
+    pub festival_year_id: Option<u64>,
+    pub festival_year: Option<String>,
+    pub festival_year_product_id: Option<u64>,
+    #[serde(default)]
+    pub genres: Vec<ExportGenre>,
+    #[serde(default)]
+    pub countries: Vec<ExportCountry>,
+    #[serde(default)]
+    pub competitions: Vec<String>,
+    /// Thumbnail urls, keyed by resolution.
+    #[serde(default)]
+    pub thumbnails: BTreeMap<String, String>,
+    /// Downloaded thumbnails, keyed by resolution.
+    #[serde(default)]
+    pub thumbnail_files: BTreeMap<String, ExportThumbnailFile>,
+    pub listed_at: Option<DateTime<Utc>>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub delisted_at: Option<DateTime<Utc>>,
+    pub download_path: Option<String>,
+    pub download_started_at: Option<DateTime<Utc>>,
+    pub download_finished_at: Option<DateTime<Utc>>,
+    pub greeting_download_path: Option<String>,
+    pub greeting_download_started_at: Option<DateTime<Utc>>,
+    pub greeting_download_finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Film> for ExportRecord {
+    fn from(film: &Film) -> Self {
+        ExportRecord {
+            id: film.id,
+            title: film.title.clone(),
+            original_title: film.original_title.clone(),
+            director: film.director.clone(),
+            production_year: film.production_year,
+            duration: film.duration,
+            description: film.description.clone(),
+            age_restriction: film.age_restriction.clone(),
+            fetched_at: film.fetched_at,
+            status: film.status.clone(),
+            vimeo_id: film.vimeo_id.clone(),
+            greeting_vimeo_id: film.greeting_vimeo_id.clone(),
+            festival_year_id: film.year.as_ref().map(|year| year.id),
+            festival_year: film.year.as_ref().and_then(|year| year.title.clone()),
+            festival_year_product_id: film.year.as_ref().and_then(|year| year.product_id),
+            genres: film
+                .genres
+                .iter()
+                .map(|genre| ExportGenre {
+                    identifier: genre.id.clone(),
+                    title: genre.title.clone(),
+                })
+                .collect(),
+            countries: film
+                .countries
+                .iter()
+                .map(|country| ExportCountry {
+                    code: country.code.clone(),
+                    title: country.title.clone(),
+                })
+                .collect(),
+            competitions: film.competitions.clone(),
+            thumbnails: film.thumbnails.iter().cloned().collect(),
+            thumbnail_files: film
+                .thumbnail_files
+                .iter()
+                .filter_map(|thumbnail| {
+                    let file = ExportThumbnailFile {
+                        url: thumbnail.url.clone(),
+                        path: thumbnail.path.clone()?,
+                        size: thumbnail.size?,
+                        sha256: thumbnail.sha256.clone()?,
+                    };
+
+                    Some((thumbnail.resolution.clone(), file))
+                })
+                .collect(),
+            listed_at: film.listed_at,
+            last_seen_at: film.last_seen_at,
+            delisted_at: film.delisted_at,
+            download_path: film.download.as_ref().map(|dl| dl.path.clone()),
+            download_started_at: film.download.as_ref().map(|dl| dl.started_at),
+            download_finished_at: film.download.as_ref().and_then(|dl| dl.finished_at),
+            greeting_download_path: film.greeting_download.as_ref().map(|dl| dl.path.clone()),
+            greeting_download_started_at: film.greeting_download.as_ref().map(|dl| dl.started_at),
+            greeting_download_finished_at: film
+                .greeting_download
+                .as_ref()
+                .and_then(|dl| dl.finished_at),
+        }
+    }
+}
+
+/// Returns the download described by the download fields of a record, if it has a path.
+///
+/// Downloads without a start time are assumed to have started when they finished.
+fn film_download(
+    path: Option<String>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+) -> Option<FilmDownload> {
+    path.map(|path| FilmDownload {
+        started_at: started_at.or(finished_at).unwrap_or_else(Utc::now),
+        finished_at,
+        path,
+    })
+}
+
+impl From<ExportRecord> for Film {
+    fn from(record: ExportRecord) -> Self {
+        let year = match record.festival_year_id {
+            Some(id) => Some(FilmYear {
+                id,
+                title: record.festival_year,
+                product_id: record.festival_year_product_id,
+            }),
+            None => None,
+        };
+        let download = film_download(
+            record.download_path,
+            record.download_started_at,
+            record.download_finished_at,
+        );
+        let greeting_download = film_download(
+            record.greeting_download_path,
+            record.greeting_download_started_at,
+            record.greeting_download_finished_at,
+        );
+
+        Film {
+            id: record.id,
+            title: record.title,
+            original_title: record.original_title,
+            director: record.director,
+            production_year: record.production_year,
+            duration: record.duration,
+            description: record.description,
+            age_restriction: record.age_restriction,
+            fetched_at: record.fetched_at,
+            status: record.status,
+            vimeo_id: record.vimeo_id,
+            greeting_vimeo_id: record.greeting_vimeo_id,
+            year,
+            listed_at: record.listed_at,
+            last_seen_at: record.last_seen_at,
+            delisted_at: record.delisted_at,
+            thumbnails: record.thumbnails.into_iter().collect(),
+            thumbnail_files: record
+                .thumbnail_files
+                .into_iter()
+                .map(|(resolution, file)| FilmThumbnail {
+                    resolution,
+                    url: file.url,
+                    path: Some(file.path),
+                    size: Some(file.size),
+                    sha256: Some(file.sha256),
+                })
+                .collect(),
+            genres: record
+                .genres
+                .into_iter()
+                .map(|genre| FilmGenre {
+                    id: genre.identifier,
+                    title: genre.title,
+                })
+                .collect(),
+            countries: record
+                .countries
+                .into_iter()
+                .map(|country| FilmCountry {
+                    title: country.title,
+                    code: country.code,
+                })
+                .collect(),
+            competitions: record.competitions,
+            download,
+            greeting_download,
+        }
+    }
+}
+
+impl ExportRecord {
+    /// Returns the value of `field` formatted for a CSV cell.
+    fn csv_field(&self, field: &str) -> String {
+        fn opt<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map_or_else(String::new, T::to_string)
+        }
+
+        fn time(value: &Option<DateTime<Utc>>) -> String {
+            value
+                .as_ref()
+                .map_or_else(String::new, DateTime::to_rfc3339)
+        }
+
+        match field {
+            "id" => self.id.to_string(),
+            "title" => opt(&self.title),
+            "original_title" => opt(&self.original_title),
+            "director" => opt(&self.director),
+            "production_year" => opt(&self.production_year),
+            "duration" => opt(&self.duration),
+            "description" => opt(&self.description),
+            "age_restriction" => opt(&self.age_restriction),
+            "fetched_at" => time(&self.fetched_at),
+            "status" => opt(&self.status),
+            "vimeo_id" => opt(&self.vimeo_id),
+            "greeting_vimeo_id" => opt(&self.greeting_vimeo_id),
+            "festival_year_id" => opt(&self.festival_year_id),
+            "festival_year" => opt(&self.festival_year),
+            "festival_year_product_id" => opt(&self.festival_year_product_id),
+            "genres" => self
+                .genres
+                .iter()
+                .map(|genre| genre.identifier.as_str())
+                .collect::<Vec<_>>()
+                .join(CSV_LIST_SEPARATOR),
+            "countries" => self
+                .countries
+                .iter()
+                .map(|country| country.code.as_str())
+                .collect::<Vec<_>>()
+                .join(CSV_LIST_SEPARATOR),
+            "competitions" => self.competitions.join(CSV_LIST_SEPARATOR),
+            "thumbnails" => self
+                .thumbnails
+                .iter()
+                .map(|(resolution, url)| format!("{}={}", resolution, url))
+                .collect::<Vec<_>>()
+                .join(CSV_LIST_SEPARATOR),
+            "thumbnail_files" => self
+                .thumbnail_files
+                .iter()
+                .map(|(resolution, file)| format!("{}={}", resolution, file.path))
+                .collect::<Vec<_>>()
+                .join(CSV_LIST_SEPARATOR),
+            "listed_at" => time(&self.listed_at),
+            "last_seen_at" => time(&self.last_seen_at),
+            "delisted_at" => time(&self.delisted_at),
+            "download_path" => opt(&self.download_path),
+            "download_started_at" => time(&self.download_started_at),
+            "download_finished_at" => time(&self.download_finished_at),
+            "greeting_download_path" => opt(&self.greeting_download_path),
+            "greeting_download_started_at" => time(&self.greeting_download_started_at),
+            "greeting_download_finished_at" => time(&self.greeting_download_finished_at),
+            _ => unreachable!("unknown export field `{}`", field),
+        }
+    }
+
+    /// Returns the record as a JSON object with only the given `fields`.
+    fn json_object(&self, fields: &[&str]) -> Result<Value, Error> {
+        let value = serde_json::to_value(self).map_err(ErrorKind::JsonSerializationFailed)?;
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => unreachable!("records always serialize to an object"),
+        };
+
+        let selected: Map<String, Value> = fields
+            .iter()
+            .filter_map(|field| {
+                object
+                    .remove(*field)
+                    .map(|value| (field.to_string(), value))
+            })
+            .collect();
+
+        Ok(Value::Object(selected))
+    }
+}
+
+/// Parses a comma-separated list of field names, returning all [`FIELDS`] if `fields` is `None`.
+///
+/// # Errors
+///
+/// Returns [`ErrorKind::UnknownExportField`] if a field name is not one of [`FIELDS`].
+pub fn parse_fields(fields: Option<&str>) -> Result<Vec<&'static str>, Error> {
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok(FIELDS.to_vec()),
+    };
+
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            FIELDS
+                .iter()
+                .find(|field| **field == name)
+                .copied()
+                .ok_or_else(|| Error::from(ErrorKind::UnknownExportField(name.to_string())))
+        })
+        .collect()
+}
+
+/// Quotes a CSV cell if it contains a separator, a quote or a line break.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes `films` to `writer` in the given `format`, including only the given `fields`.
+pub fn write_films<W: Write>(
+    mut writer: W,
+    films: &[Film],
+    format: ExportFormat,
+    fields: &[&str],
+) -> Result<(), Error> {
+    let records = films.iter().map(ExportRecord::from);
+
+    match format {
+        ExportFormat::Json => {
+            let objects = records
+                .map(|record| record.json_object(fields))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            serde_json::to_writer_pretty(&mut writer, &objects)
+                .map_err(ErrorKind::JsonSerializationFailed)?;
+            writeln!(writer)?;
+        }
+        ExportFormat::Ndjson => {
+            for record in records {
+                serde_json::to_writer(&mut writer, &record.json_object(fields)?)
+                    .map_err(ErrorKind::JsonSerializationFailed)?;
+                writeln!(writer)?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(writer, "{}", fields.join(","))?;
+
+            for record in records {
+                let row: Vec<String> = fields
+                    .iter()
+                    .map(|field| csv_escape(&record.csv_field(field)))
+                    .collect();
+
+                writeln!(writer, "{}", row.join(","))?;
+            }
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+
+use serde_json::{Map, Value};
+use tracing::{debug, instrument, trace};
+
+use crate::database::{Database, Film};
+use crate::error::{Error, ErrorKind};
+use crate::export::ExportRecord;
+
+/// Fields that record when something was fetched, listed or downloaded rather than describing
+/// the film itself, and which therefore never cause a conflict.
+///
+/// Stored listings, downloads and thumbnail files aren't overwritten either, see
+/// [`Database::save_film`](crate::database::Database::save_film).
+const BOOKKEEPING_FIELDS: &[&str] = &[
+    "fetched_at",
+    "listed_at",
+    "last_seen_at",
+    "delisted_at",
+    "thumbnail_files",
+    "download_path",
+    "download_started_at",
+    "download_finished_at",
+    "greeting_download_path",
+    "greeting_download_started_at",
+    "greeting_download_finished_at",
+];
+
+/// An imported record, along with the names of the fields it contains.
+///
+/// Exports made with a subset of the fields leave the rest out, so the fields that are absent
+/// from a record are kept as they are when it's imported over a stored film.
+#[derive(Debug, Clone)]
+pub struct ImportRecord {
+    pub record: ExportRecord,
+    pub fields: BTreeSet<String>,
+}
+
+impl ImportRecord {
+    /// Deserializes a record from a JSON object, remembering which fields it contains.
+    fn from_object(
+        object: Map<String, Value>,
+    ) -> Result<ImportRecord, serde_path_to_error::Error<serde_json::Error>> {
+        let fields = object.keys().cloned().collect();
+        let record = serde_path_to_error::deserialize(Value::Object(object))?;
+
+        Ok(ImportRecord { record, fields })
+    }
+}
+
+/// A film that already exists in the database with different details than the imported record.
+#[derive(Debug)]
+pub struct ImportConflict {
+    pub film_id: u64,
+    /// The names of the fields that differ.
+    pub fields: Vec<String>,
+}
+
+/// What happened to the records of an import.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// The number of films that weren't in the database.
+    pub new: usize,
+    /// The number of conflicting films that were overwritten.
+    pub updated: usize,
+    /// The number of films that were already in the database with the same details.
+    pub unchanged: usize,
+    /// Conflicting films, whether or not they were overwritten.
+    pub conflicts: Vec<ImportConflict>,
+}
+
+/// Reads export records from `text`, which is either a JSON array of records or one JSON record
+/// per line.
+///
+/// # Errors
+///
+/// Returns [`ErrorKind::JsonDeserializationFailed`] if a JSON array is malformed,
+/// [`ErrorKind::InvalidImportArrayRecord`] with the index if a record of the array is malformed,
+/// or [`ErrorKind::InvalidImportRecord`] with the line number if a line is malformed.
+pub fn parse_records(text: &str) -> Result<Vec<ImportRecord>, Error> {
+    if text.trim_start().starts_with('[') {
+        let jd = &mut serde_json::Deserializer::from_str(text);
+        let objects: Vec<Map<String, Value>> = serde_path_to_error::deserialize(jd)
+            .map_err(|err| Error::from(ErrorKind::JsonDeserializationFailed(err)))?;
+
+        return objects
+            .into_iter()
+            .enumerate()
+            .map(|(index, object)| {
+                ImportRecord::from_object(object)
+                    .map_err(|err| Error::from(ErrorKind::InvalidImportArrayRecord(index, err)))
+            })
+            .collect();
+    }
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let jd = &mut serde_json::Deserializer::from_str(line);
+
+            serde_path_to_error::deserialize(jd)
+                .and_then(ImportRecord::from_object)
+                .map_err(|err| Error::from(ErrorKind::InvalidImportRecord(index + 1, err)))
+        })
+        .collect()
+}
+
+/// Reads all export records from `reader`.
+pub fn read_records<R: Read>(mut reader: R) -> Result<Vec<ImportRecord>, Error> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    parse_records(&text)
+}
+
+/// Returns the fields of `record` that describe the film, as a JSON value with sorted lists so
+/// that records can be compared regardless of the order of their associations.
+fn comparable(record: &ExportRecord) -> Result<Map<String, Value>, Error> {
+    let mut record = record.clone();
+    record
+        .genres
+        .sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    record.countries.sort_by(|a, b| a.code.cmp(&b.code));
+    record.competitions.sort();
+
+    let mut object = to_object(&record)?;
+
+    for field in BOOKKEEPING_FIELDS {
+        object.remove(*field);
+    }
+
+    Ok(object)
+}
+
+/// Serializes `record` into a JSON object keyed by field name.
+fn to_object(record: &ExportRecord) -> Result<Map<String, Value>, Error> {
+    match serde_json::to_value(record).map_err(ErrorKind::JsonSerializationFailed)? {
+        Value::Object(object) => Ok(object),
+        _ => unreachable!("records always serialize to an object"),
+    }
+}
+
+/// Returns `existing` with the fields that are present in `imported` replaced by the imported
+/// ones.
+fn merge(existing: &ExportRecord, imported: &ImportRecord) -> Result<ExportRecord, Error> {
+    let mut merged = to_object(existing)?;
+    let record = to_object(&imported.record)?;
+
+    for (field, value) in record {
+        if imported.fields.contains(&field) {
+            merged.insert(field, value);
+        }
+    }
+
+    let jd = Value::Object(merged);
+
+    serde_path_to_error::deserialize(jd)
+        .map_err(|err| Error::from(ErrorKind::JsonDeserializationFailed(err)))
+}
+
+/// Returns the names of the fields that differ between `existing` and `imported`, ignoring
+/// [`BOOKKEEPING_FIELDS`].
+fn conflicting_fields(
+    existing: &ExportRecord,
+    imported: &ExportRecord,
+) -> Result<Vec<String>, Error> {
+    let existing = comparable(existing)?;
+    let imported = comparable(imported)?;
+
+    Ok(existing
+        .iter()
+        .filter(|(field, value)| imported.get(*field) != Some(*value))
+        .map(|(field, _)| field.clone())
+        .collect())
+}
+
+/// Imports `records` into `db`, skipping films that already exist with different details unless
+/// `overwrite` is set.
+///
+/// Fields that are absent from a record are left as they are on a stored film, so that exports
+/// made with `--fields` only overwrite the fields they contain.
+///
+/// The caller is expected to wrap this in a transaction.
+#[instrument(skip(db, records), fields(num_records = records.len()), err)]
+pub fn import_records(
+    db: &Database,
+    records: Vec<ImportRecord>,
+    overwrite: bool,
+) -> Result<ImportSummary, Error> {
+    let mut summary = ImportSummary::default();
+
+    for imported in records {
+        let film_id = imported.record.id;
+
+        let record = match db.get_film(film_id)? {
+            None => {
+                trace!(film_id, "Importing new film");
+
+                summary.new += 1;
+
+                imported.record
+            }
+            Some(existing) => {
+                let existing = ExportRecord::from(&existing);
+                let record = merge(&existing, &imported)?;
+                let fields = conflicting_fields(&existing, &record)?;
+
+                if fields.is_empty() {
+                    trace!(film_id, "Skipping unchanged film");
+
+                    summary.unchanged += 1;
+                    continue;
+                }
+
+                debug!(film_id, ?fields, "Imported film conflicts with stored film");
+
+                summary.conflicts.push(ImportConflict { film_id, fields });
+
+                if !overwrite {
+                    continue;
+                }
+
+                summary.updated += 1;
+
+                record
+            }
+        };
+
+        db.save_film(&Film::from(record))?;
+    }
+
+    Ok(summary)
+}
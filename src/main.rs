@@ -1,75 +1,69 @@
-#![allow(clippy::pub_enum_variant_names)]
-
+use std::collections::HashSet;
 use std::env;
-use std::path::Path;
-use std::time::Duration;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Utc};
 use clap::Clap;
 use color_eyre::eyre::{self, Error as EyreError};
-use serde_json::{Map, Value as JsonValue};
-use tokio::process::Command;
-use tokio::time::sleep;
-use tracing::{debug, debug_span, error, instrument, trace};
+use futures::{
+    stream::{self, StreamExt},
+    FutureExt,
+};
+use tracing::{debug, debug_span, error, info, instrument, trace, warn, Instrument};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::layer::SubscriberExt;
 
 mod cli;
 mod client;
 mod database;
+mod downloader;
 mod error;
-
-use client::Client;
-use database::{Database, MissingFilmDownload};
+mod export;
+mod import;
+mod migrations;
+mod mp4;
+mod nfo;
+mod print;
+mod progress;
+mod query;
+mod rate_limit;
+mod sanitize;
+mod template;
+mod thumbnail;
+mod vimeo;
+
+use client::{Client, GetFilmsResponse, RetryPolicy};
+use database::{Database, DownloadKind, Film, FilmSearch, MissingFilmDownload};
+use downloader::Downloader;
 use error::{Error, ErrorKind};
+use progress::ProgressBars;
+use query::FilmQuery;
+use template::OutputLayout;
 
-/// Given a list of `film_ids`, this will return a new list that only consists of id's not
-/// currently present in the database.
-fn film_ids_not_in_db<'a>(db: &Database, film_ids: &[&'a str]) -> Result<Vec<&'a str>, Error> {
-    let mut res = vec![];
-    let mut stmt = db.prepare("SELECT id FROM films WHERE id = ?1")?;
-
-    for id in film_ids {
-        if stmt.query([id])?.next()?.is_none() {
-            res.push(*id);
-        }
-    }
-
-    Ok(res)
-}
-
+/// Fetches the details of a film and stores them in the database, updating any previously stored
+/// details.
 #[instrument(skip(client, db), err)]
-async fn fetch_film(
-    client: &Client,
-    db: &Database,
-    film_data: &Map<String, JsonValue>,
-) -> Result<(), Error> {
-    let film_id = film_data.get("id").and_then(JsonValue::as_u64).unwrap();
-
+async fn fetch_film(client: &Client, db: &Database, film_id: u64) -> Result<(), Error> {
     debug!("Getting film details");
     let film = client.get_film(film_id).await?;
     let film_data = &film.data;
 
-    // Insert the film into the database
-    db.create_film(film_id, film_data)?;
-
-    // Insert thumbnails into the database
-    if !film_data.thumbnails.is_empty() {
-        if let Ok(stored_thumbnails) = db.get_film_thumbnails(film_id) {
-            for (key, value) in &film_data.thumbnails {
-                if !stored_thumbnails
-                    .iter()
-                    .any(|(ref resolution, _url)| resolution == key)
-                {
-                    // Insert the thumbnail
-                    match db.create_film_thumbnail(film_id, key, Some(value.as_str())) {
-                        Ok(_) => {}
-                        Err(err) => error!(?err, "Could not add thumbnail"),
-                    }
-                }
-            }
-        }
+    let tx = db.unchecked_transaction()?;
+
+    // Insert or update the film in the database
+    db.upsert_film(film_id, film_data)?;
+
+    // Replace the stored thumbnails
+    if let Err(err) = db.sync_film_thumbnails(film_id, &film_data.thumbnails) {
+        error!(?err, "Could not update thumbnails");
     }
 
+    // Associations are recreated from scratch so that removed genres, countries and
+    // competitions don't linger
+    db.clear_film_associations(film_id)?;
+
     if !film_data.genres.is_empty() {
         if let Err(err) = db.sync_genres(&film_data.genres) {
             error!("Could not upsert genres: {:?}", err);
@@ -112,156 +106,417 @@ async fn fetch_film(
         }
     }
 
-    if let Err(err) = db.create_film_year(film_id, &film_data.year) {
-        error!("Could not create film year: {:?}", err);
+    if let Err(err) = db.upsert_film_year(film_id, &film_data.year) {
+        error!("Could not upsert film year: {:?}", err);
     }
 
-    if let Err(err) = db.create_film_status(film_id, &film.status) {
-        error!("Could not create film status: {:?}", err);
+    if let Err(err) = db.upsert_film_status(film_id, &film.status) {
+        error!("Could not upsert film status: {:?}", err);
     }
 
+    tx.commit()?;
+
     Ok(())
 }
 
-/// Downloads all films that we have fetched data for, that aren't already downloaded.
-#[instrument(skip(db))]
-async fn download_missing_films(db: &Database) -> Result<(), Error> {
+/// Downloads all films that we have fetched data for, that aren't already downloaded, running up
+/// to `jobs` downloads at a time.
+#[instrument(skip(db, downloader, layout))]
+async fn download_missing_films(
+    db: &Database,
+    downloader: &dyn Downloader,
+    layout: &OutputLayout,
+    jobs: usize,
+) -> Result<(), Error> {
     let missing_downloads = db.get_missing_downloads()?;
     let num_missing_downloads = missing_downloads.len();
 
-    if num_missing_downloads > 0 {
-        debug!(num_missing_downloads, "Starting download of missing films");
+    // Incomplete films are still downloaded, but named using fallbacks for the missing details
+    let incomplete_films: Vec<&MissingFilmDownload> = missing_downloads
+        .iter()
+        .filter(|missing_download| {
+            missing_download.kind == DownloadKind::Film
+                && !missing_download.missing_fields().is_empty()
+        })
+        .collect();
+
+    for missing_download in &incomplete_films {
+        warn!(
+            film_id = missing_download.id,
+            missing_fields = ?missing_download.missing_fields(),
+            "Film is missing details, naming its download using fallbacks"
+        );
+    }
 
-        for missing_download in missing_downloads {
-            if let Err(err) = download_film(db, &missing_download).await {
-                error!(
-                    film_id = missing_download.id,
-                    film_title = missing_download.title.as_str(),
-                    "Could not download film"
+    if num_missing_downloads > 0 {
+        debug!(
+            num_missing_downloads,
+            jobs, "Starting download of missing films"
+        );
+
+        let disambiguated = &films_to_disambiguate(db, layout)?;
+        let bars = &ProgressBars::new();
+
+        stream::iter(missing_downloads.iter().enumerate())
+            .map(|(index, missing_download)| {
+                let span = debug_span!(
+                    "download_job",
+                    job = index + 1,
+                    num_jobs = num_missing_downloads
                 );
-                eprintln!("{:?}", eyre::Report::new(err));
-            }
-        }
+
+                download_film(
+                    db,
+                    downloader,
+                    layout,
+                    disambiguated,
+                    bars,
+                    missing_download,
+                )
+                .map(move |res| (missing_download, res))
+                .instrument(span)
+            })
+            .buffer_unordered(jobs.max(1))
+            .for_each(|(missing_download, res)| async move {
+                if let Err(err) = res {
+                    error!(
+                        film_id = missing_download.id,
+                        film_title = missing_download.title.as_deref().unwrap_or_default(),
+                        "Could not download film"
+                    );
+                    eprintln!("{:?}", eyre::Report::new(err));
+                }
+            })
+            .await;
     }
 
+    print::incomplete_films(&incomplete_films);
+
     Ok(())
 }
 
+/// Stores the film listing and fetches the details of every listed film that is not already in
+/// the database, running up to `concurrency` fetches at a time.
+///
+/// If `refresh_before` is given, listed films that were last fetched before then are fetched
+/// again as well.
+///
+/// Previously listed films that are absent from the listing are marked as delisted.
+///
+/// The fetches are polled concurrently on the current task rather than spawned, so database
+/// access never happens from more than one thread, and each film is written in one go between
+/// two `.await` points.
 #[instrument(skip(client, db, films), err)]
-async fn fetch_films(client: &Client, db: &Database, films: JsonValue) -> Result<(), Error> {
-    let data = films
-        .get("data")
-        .and_then(JsonValue::as_object)
-        .ok_or_else(|| {
-            Error::from(ErrorKind::ApiError(
-                "API response did not include a .data field".to_string(),
-            ))
-        })?;
-
-    let num_films = data.len();
+async fn fetch_films(
+    client: &Client,
+    db: &Database,
+    films: &GetFilmsResponse,
+    concurrency: usize,
+    refresh_before: Option<DateTime<Utc>>,
+) -> Result<(), Error> {
+    let num_films = films.data.len();
     debug!("Received a list containing {} films", num_films);
 
-    let film_ids: Vec<&str> = data.keys().map(String::as_str).collect();
-    let missing_film_ids = film_ids_not_in_db(db, &film_ids)?;
+    let seen_at = Utc::now();
+    let mut num_failed_listings = 0;
+
+    for film in films.data.values() {
+        if let Err(err) = db.upsert_film_listing(film, seen_at) {
+            error!(film_id = film.id, ?err, "Could not store film listing");
+
+            num_failed_listings += 1;
+        }
+    }
+
+    // An empty listing is more likely to be an API hiccup than the whole catalog going away, and
+    // films whose listing could not be stored would look absent from the listing
+    if films.data.is_empty() {
+        warn!("The film listing is empty, not marking any films as delisted");
+    } else if num_failed_listings > 0 {
+        warn!(
+            num_failed_listings,
+            "Some film listings could not be stored, not marking any films as delisted"
+        );
+    } else {
+        let num_delisted = db.mark_delisted_films(seen_at)?;
+
+        if num_delisted > 0 {
+            info!(
+                num_delisted,
+                "Marked films absent from the listing as delisted"
+            );
+        }
+    }
+
+    let film_ids: Vec<u64> = films.data.values().map(|film| film.id).collect();
+    let film_ids_to_fetch = db.get_film_ids_to_fetch(&film_ids, refresh_before)?;
 
     debug!(
-        "Of those films, {} are not present in our database",
-        missing_film_ids.len()
+        ?refresh_before,
+        "Of those films, {} are either missing from our database or due for a refresh",
+        film_ids_to_fetch.len()
     );
 
-    for missing_id in missing_film_ids {
-        if let Some(object) = data.get(missing_id).and_then(JsonValue::as_object) {
-            if let Err(error) = fetch_film(client, db, object).await {
+    stream::iter(film_ids_to_fetch)
+        .map(|film_id| async move { (film_id, fetch_film(client, db, film_id).await) })
+        .buffer_unordered(concurrency.max(1))
+        .for_each(|(film_id, res)| async move {
+            if let Err(error) = res {
                 error!(
-                    film_id = missing_id,
+                    film_id,
                     error = ?error,
                     "Could not fetch the film"
                 );
                 eprintln!("{:?}", eyre::Report::new(error));
             }
+        })
+        .await;
 
-            sleep(Duration::from_secs(1)).await;
-        } else {
-            error!("Could not extract data field from film object");
+    Ok(())
+}
+
+/// Archives the thumbnails of every film with thumbnails that haven't been downloaded yet, next
+/// to where the film is or will be downloaded.
+///
+/// This is independent of whether the video of the film has been downloaded, so thumbnails are
+/// archived even if the video can't be, and films downloaded before thumbnails were archived get
+/// theirs as well.
+#[instrument(skip(db, layout), err)]
+async fn download_missing_thumbnails(db: &Database, layout: &OutputLayout) -> Result<(), Error> {
+    let film_ids = db.get_films_with_missing_thumbnails()?;
+
+    if film_ids.is_empty() {
+        return Ok(());
+    }
+
+    debug!(
+        num_films = film_ids.len(),
+        "Starting download of missing thumbnails"
+    );
+
+    let http = reqwest::Client::builder()
+        .build()
+        .map_err(ErrorKind::HttpClientFailed)?;
+    let disambiguated = films_to_disambiguate(db, layout)?;
+
+    for film_id in film_ids {
+        let film = db
+            .get_film(film_id)?
+            .ok_or(ErrorKind::FilmNotFound(film_id))?;
+        let video_path = match &film.download {
+            Some(download) => PathBuf::from(&download.path),
+            None => film_output_path(layout, &film, DownloadKind::Film, &disambiguated),
+        };
+
+        if let Err(err) = thumbnail::download_thumbnails(&http, db, film_id, &video_path).await {
+            error!(film_id, "Could not download thumbnails: {:?}", err);
         }
     }
 
     Ok(())
 }
 
-#[instrument(skip(db), err)]
-async fn download_film(db: &Database, film: &MissingFilmDownload) -> Result<(), Error> {
-    let film_status = db.get_film_status(film.id)?;
+/// Returns the ids of the films whose downloads must have their id in the filename to tell them
+/// apart from other films, as decided by [`OutputLayout::films_to_disambiguate`].
+///
+/// This is decided for all films up front, so that the names don't depend on the order the films
+/// are downloaded in.
+fn films_to_disambiguate(db: &Database, layout: &OutputLayout) -> Result<HashSet<u64>, Error> {
+    let films = db.query_films(&FilmQuery::new())?;
+    let film_ids = layout.films_to_disambiguate(&films);
+
+    if !film_ids.is_empty() {
+        debug!(
+            num_films = film_ids.len(),
+            "Films share a path with other films"
+        );
+    }
 
-    let vimeo_url = format!(
-        "https://player.vimeo.com/video/{}?app_id=122963",
-        film_status.vimeo_id
-    );
-    let filename = format!(
-        "{} - {} ({}).mp4",
-        film.director, film.title, film.production_year
-    );
-    let output_path = Path::new("films")
-        .join(film.production_year.to_string())
-        .join(filename);
-    let output_path_str = output_path.to_string_lossy().into_owned();
+    Ok(film_ids)
+}
 
-    let span = debug_span!(
-        "download_film",
+/// Returns the path to download the given `kind` of `film` to.
+///
+/// Films in `disambiguated` get their id added to the filename, for the greeting too, so that the
+/// names of the film and its greeting match.
+fn film_output_path(
+    layout: &OutputLayout,
+    film: &Film,
+    kind: DownloadKind,
+    disambiguated: &HashSet<u64>,
+) -> PathBuf {
+    layout.film_path(film, kind, disambiguated.contains(&film.id))
+}
+
+#[instrument(
+    skip(db, downloader, layout, disambiguated, bars, film),
+    fields(
         film_id = film.id,
-        film_title = film.title.as_str()
-    );
-    let _enter = span.enter();
+        film_title = film.title.as_deref().unwrap_or_default(),
+        kind = film.kind.as_str(),
+        percent = tracing::field::Empty,
+        speed = tracing::field::Empty,
+        eta = tracing::field::Empty,
+        fragment_index = tracing::field::Empty,
+        fragment_count = tracing::field::Empty
+    ),
+    err
+)]
+async fn download_film(
+    db: &Database,
+    downloader: &dyn Downloader,
+    layout: &OutputLayout,
+    disambiguated: &HashSet<u64>,
+    bars: &ProgressBars,
+    film: &MissingFilmDownload,
+) -> Result<(), Error> {
+    let film_status = db.get_film_status(film.id)?;
+    let vimeo_id = match film.kind {
+        DownloadKind::Film => film_status.vimeo_id,
+        DownloadKind::Greeting => film_status.greeting_vimeo_id,
+    };
+    let vimeo_id = match vimeo_id {
+        Some(vimeo_id) => vimeo_id,
+        None => {
+            warn!("There is no vimeo id to download");
+
+            return Ok(());
+        }
+    };
+
+    let details = db
+        .get_film(film.id)?
+        .ok_or(ErrorKind::FilmNotFound(film.id))?;
+
+    let output_path = film_output_path(layout, &details, film.kind, disambiguated);
+    let output_path_str = output_path.to_string_lossy().into_owned();
 
     debug!(
-        film_director = film.director.as_str(),
+        film_director = film.director.as_deref().unwrap_or_default(),
         ?output_path,
         "Downloading film"
     );
 
-    let args = [
-        "--referer",
-        "https://offstream.dk/",
-        "-f",
-        "bestvideo+bestaudio",
-        "--merge-output-format",
-        "mp4",
-        "-o",
-        output_path_str.as_str(),
-        vimeo_url.as_str(),
-    ];
-
-    debug!(?args, "Running youtube-dl");
-
-    let mut cmd = Command::new("youtube-dl");
-    cmd.args(args.iter());
-
-    db.upsert_film_download(film.id, false, Some(output_path_str.as_str()))?;
-
-    let res = cmd
-        .spawn()
-        .map_err(|err| {
-            Error::from(ErrorKind::YouTubeDlError(format!(
-                "Could not create process: {}",
-                err
-            )))
-        })?
-        .wait()
-        .await;
+    db.upsert_film_download(film.id, film.kind, false, Some(output_path_str.as_str()))?;
+
+    let file_name = output_path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let progress = bars.add(&file_name);
+    let attempt_id = db.start_download_attempt(film.id, film.kind, downloader.name())?;
+
+    match downloader
+        .download(&vimeo_id, &output_path, &progress)
+        .await
+    {
+        Ok(()) => {
+            progress.finish();
+            debug!("{} finished successfully", downloader.name());
+
+            db.finish_download_attempt(attempt_id, None)?;
+            db.upsert_film_download(film.id, film.kind, true, Some(output_path_str.as_str()))?;
+
+            if film.kind == DownloadKind::Film {
+                if let Err(err) = nfo::write_sidecar(&details, &output_path) {
+                    error!("Could not write .nfo sidecar: {:?}", err);
+                }
+            }
+        }
+        Err(err) => {
+            progress.fail();
 
-    if let Ok(exit) = res {
-        if exit.success() {
-            debug!("youtube-dl finished successfully");
+            let failure = downloader::attempt_failure(&err);
 
-            db.upsert_film_download(film.id, true, Some(output_path_str.as_str()))?;
-        } else {
-            debug!("youtube-dl failed: {}", exit);
+            debug!(
+                category = failure.category.as_str(),
+                exit_status = ?failure.exit_status,
+                "{} failed",
+                downloader.name()
+            );
+
+            db.finish_download_attempt(attempt_id, Some(&failure))?;
+
+            return Err(err);
         }
     }
 
     Ok(())
 }
 
+/// Applies pending migrations to the database at `path`, or prints the status of each migration
+/// if `status_only` is set.
+fn migrate<P: AsRef<Path>>(path: P, status_only: bool) -> Result<(), Error> {
+    let db = Database::open(path)?;
+
+    if !status_only {
+        migrations::migrate(&db)?;
+    }
+
+    let version = migrations::schema_version(&db)?;
+    let pending_migrations = migrations::pending_migrations(&db)?;
+
+    println!("Schema version: {}", version);
+
+    for migration in migrations::MIGRATIONS {
+        let status = if pending_migrations
+            .iter()
+            .any(|pending| pending.version == migration.version)
+        {
+            "pending"
+        } else {
+            "applied"
+        };
+
+        println!("{:04} {:<32} {}", migration.version, migration.name, status);
+    }
+
+    Ok(())
+}
+
+/// Fetches the film listing and the details of new films, or films due for a refresh.
+async fn sync(db: &Database, sync_opts: cli::SyncOpts) -> Result<(), Error> {
+    let client_opts = sync_opts.client_opts;
+    let client = Client::builder()
+        .base_url(client_opts.base_url)
+        .origin(client_opts.origin)
+        .retry_policy(RetryPolicy {
+            max_attempts: client_opts.retry_max_attempts,
+            base_delay: client_opts.retry_base_delay,
+            max_delay: client_opts.retry_max_delay,
+            jitter: client_opts.retry_jitter,
+        })
+        .rate_limit(client_opts.requests_per_second, client_opts.burst)
+        .build()?;
+
+    client.update_xsrf_token().await?;
+
+    // Fetch a list of films
+    let films = client.get_films().await?;
+    trace!(?films, "Retrieved list of films");
+
+    let refresh_before = if sync_opts.refresh {
+        Some(Utc::now())
+    } else {
+        // An age too large to be represented refreshes nothing
+        sync_opts.refresh_older_than.and_then(|age| {
+            chrono::Duration::from_std(age)
+                .ok()
+                .and_then(|age| Utc::now().checked_sub_signed(age))
+        })
+    };
+
+    // Fetch all missing films
+    fetch_films(
+        &client,
+        db,
+        &films,
+        sync_opts.fetch_concurrency,
+        refresh_before,
+    )
+    .await
+}
+
 fn init_tracing(jaeger_opts: cli::JaegerOpts) -> Result<(), EyreError> {
     if jaeger_opts.enabled {
         // Install a new OpenTelemetry trace pipeline
@@ -303,20 +558,136 @@ async fn main() -> Result<(), EyreError> {
     // Set up stdout or jaeger tracing
     init_tracing(opts.jaeger_opts)?;
 
-    let db = database::open(opts.database_path)?;
-    let mut client = Client::new().unwrap();
+    let database_path = opts.database_path;
 
-    client.update_xsrf_token().await?;
+    match opts.command {
+        cli::Command::Run(run_opts) => {
+            let db = database::open(&database_path)?;
+            let download_opts = run_opts.download_opts;
 
-    // Fetch a list of films
-    let films = client.get_films().await?;
-    trace!(?films, "Retrieved list of films");
+            // Find the downloader first, so that a missing one is reported before syncing
+            let downloader = downloader::detect(
+                download_opts.downloader,
+                download_opts.downloader_path.as_deref(),
+                download_opts.downloader_args,
+                &download_opts.vimeo_player_url,
+            )
+            .await?;
+            let layout = download_opts.output_opts.layout();
 
-    // Fetch all missing films
-    fetch_films(&client, &db, films).await?;
+            sync(&db, run_opts.sync_opts).await?;
+
+            // Download all films not already downloaded
+            download_missing_films(&db, downloader.as_ref(), &layout, download_opts.jobs).await?;
+            download_missing_thumbnails(&db, &layout).await?;
+        }
+        cli::Command::Sync(sync_opts) => {
+            let db = database::open(&database_path)?;
+
+            sync(&db, sync_opts).await?;
+        }
+        cli::Command::Download(download_opts) => {
+            let db = database::open(&database_path)?;
+            let downloader = downloader::detect(
+                download_opts.downloader,
+                download_opts.downloader_path.as_deref(),
+                download_opts.downloader_args,
+                &download_opts.vimeo_player_url,
+            )
+            .await?;
+            let layout = download_opts.output_opts.layout();
+
+            download_missing_films(&db, downloader.as_ref(), &layout, download_opts.jobs).await?;
+            download_missing_thumbnails(&db, &layout).await?;
+        }
+        cli::Command::List(list_opts) => {
+            let db = database::open(&database_path)?;
+            let mut query = list_opts
+                .filter_opts
+                .apply(FilmQuery::new())
+                .sort(list_opts.sort, list_opts.desc);
+
+            if let Some(limit) = list_opts.limit {
+                query = query.limit(limit);
+            }
+
+            if let Some(offset) = list_opts.offset {
+                query = query.offset(offset);
+            }
 
-    // Download all films not already downloaded
-    download_missing_films(&db).await?;
+            print::film_list(&db.query_films(&query)?);
+        }
+        cli::Command::Show(show_opts) => {
+            let db = database::open(&database_path)?;
+            let film = db
+                .get_film(show_opts.film_id)?
+                .ok_or_else(|| Error::from(ErrorKind::FilmNotFound(show_opts.film_id)))?;
+
+            print::film_details(&film);
+            print::download_attempts(&db.get_download_attempts(film.id)?);
+        }
+        cli::Command::Search(search_opts) => {
+            let db = database::open(&database_path)?;
+            let query = search_opts.query.join(" ");
+            let search = FilmSearch {
+                query: if search_opts.raw {
+                    query
+                } else {
+                    database::fts_literal_query(&query)
+                },
+                genre: search_opts.genre,
+                country: search_opts.country,
+                year: search_opts.year,
+                competition: search_opts.competition,
+                limit: search_opts.limit,
+            };
+
+            print::search_results(&db.search_films(&search)?);
+        }
+        cli::Command::Export(export_opts) => {
+            let db = database::open(&database_path)?;
+            let fields = export::parse_fields(export_opts.fields.as_deref())?;
+            let query = export_opts.filter_opts.apply(FilmQuery::new());
+            let films = db.query_films(&query)?;
+
+            match export_opts.output {
+                Some(path) => {
+                    let file = BufWriter::new(File::create(path)?);
+
+                    export::write_films(file, &films, export_opts.format, &fields)?;
+                }
+                None => {
+                    let stdout = io::stdout();
+
+                    export::write_films(stdout.lock(), &films, export_opts.format, &fields)?;
+                }
+            }
+        }
+        cli::Command::Import(import_opts) => {
+            let db = database::open(&database_path)?;
+            let records = match import_opts.input {
+                Some(path) => import::read_records(File::open(path)?)?,
+                None => import::read_records(io::stdin().lock())?,
+            };
+
+            let tx = db.unchecked_transaction()?;
+            let summary = import::import_records(&db, records, import_opts.overwrite)?;
+
+            // Dropping the transaction rolls it back
+            if !import_opts.dry_run {
+                tx.commit()?;
+            }
+
+            print::import_summary(&summary, import_opts.overwrite, import_opts.dry_run);
+        }
+        cli::Command::History(history_opts) => {
+            let db = database::open(&database_path)?;
+            let history = db.get_film_history(history_opts.film_id)?;
+
+            print::film_history(history_opts.film_id, &history);
+        }
+        cli::Command::Migrate(migrate_opts) => migrate(&database_path, migrate_opts.status)?,
+    }
 
     Ok(())
 }
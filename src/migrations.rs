@@ -0,0 +1,284 @@
+use rusqlite::Connection;
+use tracing::{debug, instrument, trace};
+
+use crate::error::{Error, ErrorKind};
+
+/// A schema migration that is embedded in the binary.
+#[derive(Debug)]
+pub struct Migration {
+    /// The schema version the database is at after applying this migration.
+    pub version: u32,
+    /// A short description of the migration.
+    pub name: &'static str,
+    /// The SQL statements to execute.
+    pub sql: &'static str,
+}
+
+/// All known migrations, ordered by version.
+///
+/// New migrations must be appended with the next version number - existing migrations must never
+/// be changed once released, as databases in the wild have already applied them.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "film_listings",
+        sql: include_str!("migrations/0002_film_listings.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "film_refresh",
+        sql: include_str!("migrations/0003_film_refresh.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "film_history",
+        sql: include_str!("migrations/0004_film_history.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "film_delisting",
+        sql: include_str!("migrations/0005_film_delisting.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "film_search",
+        sql: include_str!("migrations/0006_film_search.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "thumbnail_files",
+        sql: include_str!("migrations/0007_thumbnail_files.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "download_kinds",
+        sql: include_str!("migrations/0008_download_kinds.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "download_attempts",
+        sql: include_str!("migrations/0009_download_attempts.sql"),
+    },
+];
+
+/// Returns the latest known schema version.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Returns the schema version of the database, as stored in `PRAGMA user_version`.
+pub fn schema_version(conn: &Connection) -> Result<u32, Error> {
+    let version = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    Ok(version)
+}
+
+/// Returns the migrations that have not yet been applied to the database.
+///
+/// # Errors
+///
+/// Returns [`ErrorKind::UnknownSchemaVersion`] if the database was migrated by a newer version
+/// of this program.
+pub fn pending_migrations(conn: &Connection) -> Result<Vec<&'static Migration>, Error> {
+    let version = schema_version(conn)?;
+    let latest_version = latest_version();
+
+    if version > latest_version {
+        return Err(Error::from(ErrorKind::UnknownSchemaVersion(
+            version,
+            latest_version,
+        )));
+    }
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+        .collect())
+}
+
+/// Applies all pending migrations to the database, each in its own transaction.
+#[instrument(err, skip(conn))]
+pub fn migrate(conn: &Connection) -> Result<(), Error> {
+    for migration in pending_migrations(conn)? {
+        debug!(
+            version = migration.version,
+            name = migration.name,
+            "Applying migration"
+        );
+
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", migration.version))?;
+        tx.commit()?;
+    }
+
+    trace!(
+        version = schema_version(conn)?,
+        "Database schema is up to date"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{params, Connection};
+
+    use super::*;
+
+    /// Returns an in-memory database with the schema created by `init.sql` before migrations
+    /// were introduced, which has no schema version.
+    fn unversioned_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("migrations/fixtures/init.sql"))
+            .unwrap();
+
+        conn
+    }
+
+    fn count(conn: &Connection, sql: &str) -> u32 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migrates_unversioned_database() {
+        let conn = unversioned_database();
+
+        conn.execute_batch(
+            "
+            INSERT INTO films (id, title, director, production_year)
+            VALUES (1, 'Alpha', 'Someone', 2020), (2, 'Beta', 'Someone Else', 2021);
+
+            INSERT INTO film_status (film_id, status, vimeo_id, greeting_vimeo_id)
+            VALUES (1, 'ok', '123', '456'), (2, 'ok', '789', NULL);
+
+            INSERT INTO film_thumbnails (film_id, resolution, url)
+            VALUES (1, '640', 'https://example.com/old.jpg'),
+                   (1, '640', 'https://example.com/new.jpg'),
+                   (1, '1280', 'https://example.com/large.jpg');
+
+            INSERT INTO film_competitions (film_id, name)
+            VALUES (1, 'Danish'), (1, 'Danish'), (2, 'International');
+
+            INSERT INTO genres (id, identifier, title) VALUES (1, 'drama', 'Drama');
+            INSERT INTO film_genres (film_id, genre_id) VALUES (1, 1), (2, 1);
+
+            INSERT INTO countries (id, title, code) VALUES (1, 'Denmark', 'DK'), (2, 'Denmark', 'DK');
+            INSERT INTO film_countries (film_id, country_id) VALUES (1, 1), (2, 2);
+
+            INSERT INTO film_downloads (film_id, started_at, finished_at, path)
+            VALUES (1, '2021-01-01 00:00:00', '2021-01-01 01:00:00', 'films/alpha.mp4');
+            ",
+        )
+        .unwrap();
+
+        migrate(&conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        assert!(pending_migrations(&conn).unwrap().is_empty());
+
+        // Duplicate thumbnails and competitions are removed, keeping the latest thumbnail
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM film_thumbnails"), 2);
+        let url: String = conn
+            .query_row(
+                "SELECT url FROM film_thumbnails WHERE film_id = 1 AND resolution = '640'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(url, "https://example.com/new.jpg");
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM film_competitions"), 2);
+
+        // Everything else is kept as it was
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM films"), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM film_genres"), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM film_countries"), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM countries"), 2);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM films_fts WHERE films_fts MATCH 'alpha'"
+            ),
+            1
+        );
+
+        // Films fetched before listings were stored are listed
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM film_listings WHERE delisted_at IS NULL"
+            ),
+            2
+        );
+
+        // Existing downloads become film downloads
+        let (kind, path): (String, String) = conn
+            .query_row(
+                "SELECT kind, path FROM film_downloads WHERE film_id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(kind, "film");
+        assert_eq!(path, "films/alpha.mp4");
+
+        // The unique indexes hold
+        let insert = |sql: &str| conn.execute(sql, []);
+        assert!(insert(
+            "INSERT INTO film_thumbnails (film_id, resolution, url) VALUES (1, '640', 'x')"
+        )
+        .is_err());
+        assert!(
+            insert("INSERT INTO film_competitions (film_id, name) VALUES (1, 'Danish')").is_err()
+        );
+        assert!(
+            insert("INSERT INTO genres (identifier, title) VALUES ('drama', 'Drama')").is_err()
+        );
+        assert!(insert("INSERT INTO film_genres (film_id, genre_id) VALUES (1, 1)").is_err());
+        assert!(insert("INSERT INTO film_countries (film_id, country_id) VALUES (1, 1)").is_err());
+        assert!(conn
+            .execute(
+                "INSERT INTO film_downloads (film_id, kind, path) VALUES (?, ?, ?)",
+                params!(1, "film", "films/other.mp4"),
+            )
+            .is_err());
+        assert!(conn
+            .execute(
+                "INSERT INTO film_downloads (film_id, kind, path) VALUES (?, ?, ?)",
+                params!(1, "greeting", "films/alpha-featurette.mp4"),
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let conn = unversioned_database();
+
+        migrate(&conn).unwrap();
+        migrate(&conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn rejects_newer_schema_version() {
+        let conn = unversioned_database();
+        let newer_version = latest_version() + 1;
+
+        conn.execute_batch(&format!("PRAGMA user_version = {}", newer_version))
+            .unwrap();
+
+        let err = pending_migrations(&conn).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            ErrorKind::UnknownSchemaVersion(newer_version, latest_version()).to_string()
+        );
+        assert!(migrate(&conn).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), newer_version);
+    }
+}
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS film_listings (
+    film_id INTEGER PRIMARY KEY,
+    title VARCHAR,
+    original_title VARCHAR,
+    director VARCHAR,
+    production_year INTEGER,
+    duration INTEGER,
+    listed_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
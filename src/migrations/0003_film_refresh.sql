@@ -0,0 +1,14 @@
+ALTER TABLE films ADD COLUMN fetched_at DATETIME;
+
+DELETE FROM film_thumbnails
+WHERE id NOT IN (
+    SELECT MAX(id) FROM film_thumbnails GROUP BY film_id, resolution
+);
+
+DELETE FROM film_competitions
+WHERE rowid NOT IN (
+    SELECT MAX(rowid) FROM film_competitions GROUP BY film_id, name
+);
+
+CREATE UNIQUE INDEX idx_film_thumbnails_resolution ON film_thumbnails (film_id, resolution);
+CREATE UNIQUE INDEX idx_film_competitions ON film_competitions (film_id, name);
@@ -0,0 +1,80 @@
+CREATE TABLE film_history (
+    id INTEGER PRIMARY KEY,
+    film_id INTEGER NOT NULL REFERENCES films (id) ON DELETE CASCADE,
+    field VARCHAR NOT NULL,
+    old_value VARCHAR,
+    new_value VARCHAR,
+    changed_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_film_history ON film_history (film_id);
+
+CREATE TRIGGER film_history_films_title AFTER UPDATE OF title ON films
+WHEN OLD.title IS NOT NEW.title
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'title', OLD.title, NEW.title);
+END;
+
+CREATE TRIGGER film_history_films_original_title AFTER UPDATE OF original_title ON films
+WHEN OLD.original_title IS NOT NEW.original_title
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'original_title', OLD.original_title, NEW.original_title);
+END;
+
+CREATE TRIGGER film_history_films_director AFTER UPDATE OF director ON films
+WHEN OLD.director IS NOT NEW.director
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'director', OLD.director, NEW.director);
+END;
+
+CREATE TRIGGER film_history_films_production_year AFTER UPDATE OF production_year ON films
+WHEN OLD.production_year IS NOT NEW.production_year
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'production_year', OLD.production_year, NEW.production_year);
+END;
+
+CREATE TRIGGER film_history_films_duration AFTER UPDATE OF duration ON films
+WHEN OLD.duration IS NOT NEW.duration
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'duration', OLD.duration, NEW.duration);
+END;
+
+CREATE TRIGGER film_history_films_description AFTER UPDATE OF description ON films
+WHEN OLD.description IS NOT NEW.description
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'description', OLD.description, NEW.description);
+END;
+
+CREATE TRIGGER film_history_films_age_restriction AFTER UPDATE OF age_restriction ON films
+WHEN OLD.age_restriction IS NOT NEW.age_restriction
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.id, 'age_restriction', OLD.age_restriction, NEW.age_restriction);
+END;
+
+CREATE TRIGGER film_history_film_status_status AFTER UPDATE OF status ON film_status
+WHEN OLD.status IS NOT NEW.status
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.film_id, 'status', OLD.status, NEW.status);
+END;
+
+CREATE TRIGGER film_history_film_status_vimeo_id AFTER UPDATE OF vimeo_id ON film_status
+WHEN OLD.vimeo_id IS NOT NEW.vimeo_id
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.film_id, 'vimeo_id', OLD.vimeo_id, NEW.vimeo_id);
+END;
+
+CREATE TRIGGER film_history_film_status_greeting_vimeo_id AFTER UPDATE OF greeting_vimeo_id ON film_status
+WHEN OLD.greeting_vimeo_id IS NOT NEW.greeting_vimeo_id
+BEGIN
+    INSERT INTO film_history (film_id, field, old_value, new_value)
+    VALUES (NEW.film_id, 'greeting_vimeo_id', OLD.greeting_vimeo_id, NEW.greeting_vimeo_id);
+END;
@@ -0,0 +1,11 @@
+ALTER TABLE film_listings ADD COLUMN last_seen_at DATETIME;
+ALTER TABLE film_listings ADD COLUMN delisted_at DATETIME;
+
+UPDATE film_listings SET last_seen_at = listed_at;
+
+-- Films fetched before listings were stored need a listing to be marked as delisted
+INSERT INTO film_listings
+    (film_id, title, original_title, director, production_year, duration)
+SELECT id, title, original_title, director, production_year, duration
+FROM films
+WHERE id NOT IN (SELECT film_id FROM film_listings);
@@ -0,0 +1,31 @@
+CREATE VIRTUAL TABLE films_fts USING fts5(
+    title,
+    original_title,
+    director,
+    description,
+    content = 'films',
+    content_rowid = 'id',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO films_fts (films_fts) VALUES ('rebuild');
+
+CREATE TRIGGER films_fts_insert AFTER INSERT ON films
+BEGIN
+    INSERT INTO films_fts (rowid, title, original_title, director, description)
+    VALUES (NEW.id, NEW.title, NEW.original_title, NEW.director, NEW.description);
+END;
+
+CREATE TRIGGER films_fts_delete AFTER DELETE ON films
+BEGIN
+    INSERT INTO films_fts (films_fts, rowid, title, original_title, director, description)
+    VALUES ('delete', OLD.id, OLD.title, OLD.original_title, OLD.director, OLD.description);
+END;
+
+CREATE TRIGGER films_fts_update AFTER UPDATE OF title, original_title, director, description ON films
+BEGIN
+    INSERT INTO films_fts (films_fts, rowid, title, original_title, director, description)
+    VALUES ('delete', OLD.id, OLD.title, OLD.original_title, OLD.director, OLD.description);
+    INSERT INTO films_fts (rowid, title, original_title, director, description)
+    VALUES (NEW.id, NEW.title, NEW.original_title, NEW.director, NEW.description);
+END;
@@ -0,0 +1,4 @@
+ALTER TABLE film_thumbnails ADD COLUMN path VARCHAR;
+ALTER TABLE film_thumbnails ADD COLUMN size INTEGER;
+ALTER TABLE film_thumbnails ADD COLUMN sha256 VARCHAR;
+ALTER TABLE film_thumbnails ADD COLUMN downloaded_at DATETIME;
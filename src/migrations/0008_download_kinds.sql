@@ -0,0 +1,22 @@
+-- Films can have more than one download, e.g. a greeting from the producer, so the downloads are
+-- keyed by the film and the kind of download instead of just the film
+CREATE TABLE film_downloads_new (
+    id INTEGER PRIMARY KEY,
+    film_id INTEGER REFERENCES films (id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL DEFAULT 'film',
+    started_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    finished_at DATETIME,
+    path VARCHAR NOT NULL,
+    UNIQUE (film_id, kind)
+);
+
+INSERT INTO film_downloads_new
+    (id, film_id, kind, started_at, finished_at, path)
+SELECT id, film_id, 'film', started_at, finished_at, path
+FROM film_downloads;
+
+DROP TABLE film_downloads;
+
+ALTER TABLE film_downloads_new RENAME TO film_downloads;
+
+CREATE INDEX IF NOT EXISTS idx_film_downloads ON film_downloads (film_id);
@@ -0,0 +1,16 @@
+-- Every run of a downloader is recorded, so that failed downloads can be told apart from ones
+-- that were interrupted and it's known why they failed
+CREATE TABLE download_attempts (
+    id INTEGER PRIMARY KEY,
+    film_id INTEGER REFERENCES films (id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL,
+    downloader VARCHAR NOT NULL,
+    started_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    finished_at DATETIME,
+    exit_status INTEGER,
+    stderr_tail TEXT,
+    error_category VARCHAR,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_download_attempts ON download_attempts (film_id);
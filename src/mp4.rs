@@ -0,0 +1,453 @@
+use std::convert::TryFrom;
+
+use crate::error::{Error, ErrorKind};
+
+/// The size of a box header without a 64-bit size.
+const HEADER_LEN: usize = 8;
+
+/// A box within a buffer.
+#[derive(Debug, Clone, Copy)]
+struct Mp4Box {
+    kind: [u8; 4],
+    /// The offset of the box, including its header.
+    start: usize,
+    /// The offset of the contents of the box, after its header.
+    content_start: usize,
+    /// The offset right after the box.
+    end: usize,
+}
+
+fn invalid(reason: &str) -> Error {
+    ErrorKind::InvalidMp4(reason.to_string()).into()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| invalid("truncated box"))
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) -> Result<(), Error> {
+    data.get_mut(offset..offset + 4)
+        .ok_or_else(|| invalid("truncated box"))?
+        .copy_from_slice(&value.to_be_bytes());
+
+    Ok(())
+}
+
+/// Returns the boxes in `data[start..end]`.
+fn boxes(data: &[u8], start: usize, end: usize) -> Result<Vec<Mp4Box>, Error> {
+    let mut res = vec![];
+    let mut offset = start;
+
+    while offset < end {
+        if offset + HEADER_LEN > end {
+            return Err(invalid("truncated box header"));
+        }
+
+        let size = read_u32(data, offset)? as usize;
+        let kind = [
+            data[offset + 4],
+            data[offset + 5],
+            data[offset + 6],
+            data[offset + 7],
+        ];
+
+        let (content_start, size) = match size {
+            // The box extends to the end of the buffer
+            0 => (offset + HEADER_LEN, end - offset),
+            // The size is stored as a 64-bit integer after the type
+            1 => {
+                let high = read_u32(data, offset + 8)? as u64;
+                let low = read_u32(data, offset + 12)? as u64;
+                let size = usize::try_from((high << 32) | low)
+                    .map_err(|_| invalid("box exceeds its parent"))?;
+
+                (offset + 16, size)
+            }
+            size => (offset + HEADER_LEN, size),
+        };
+
+        // Sizes are untrusted, so the end of the box may not even be addressable
+        let box_end = offset
+            .checked_add(size)
+            .filter(|box_end| *box_end <= end && *box_end >= content_start)
+            .ok_or_else(|| invalid("box exceeds its parent"))?;
+
+        res.push(Mp4Box {
+            kind,
+            start: offset,
+            content_start,
+            end: box_end,
+        });
+
+        offset = box_end;
+    }
+
+    Ok(res)
+}
+
+/// Returns the first box of the given `kind` in `data[start..end]`.
+fn find_box(data: &[u8], start: usize, end: usize, kind: &[u8; 4]) -> Result<Mp4Box, Error> {
+    boxes(data, start, end)?
+        .into_iter()
+        .find(|b| &b.kind == kind)
+        .ok_or_else(|| invalid(&format!("missing `{}` box", String::from_utf8_lossy(kind))))
+}
+
+/// Appends a box of the given `kind` with the given `contents` to `out`.
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], contents: &[u8]) {
+    out.extend_from_slice(&((contents.len() + HEADER_LEN) as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(contents);
+}
+
+/// Returns the offset of the track id within a `tkhd` box.
+fn tkhd_track_id_offset(data: &[u8], tkhd: &Mp4Box) -> usize {
+    // Version 1 has 64-bit creation and modification times
+    if data.get(tkhd.content_start) == Some(&1) {
+        tkhd.content_start + 4 + 16
+    } else {
+        tkhd.content_start + 4 + 8
+    }
+}
+
+/// Merges the init segments of a fragmented MP4 video stream and a fragmented MP4 audio stream,
+/// as served by DASH and HLS, into a single init segment with one track each.
+///
+/// The fragments of the audio stream must then be given the track id of the audio track with
+/// [`FragmentWriter::rewrite`]. Fragments are otherwise copied as they are, since the offsets of
+/// their samples are relative to their own `moof` box.
+///
+/// Returns the merged init segment and the track id given to the audio track.
+///
+/// # Errors
+///
+/// Returns [`ErrorKind::InvalidMp4`] if either init segment isn't a fragmented MP4.
+pub fn merge_init_segments(video: &[u8], audio: &[u8]) -> Result<(Vec<u8>, u32), Error> {
+    let video_moov = find_box(video, 0, video.len(), b"moov")?;
+    let audio_moov = find_box(audio, 0, audio.len(), b"moov")?;
+    let video_children = boxes(video, video_moov.content_start, video_moov.end)?;
+    let audio_children = boxes(audio, audio_moov.content_start, audio_moov.end)?;
+
+    let mut max_track_id = 0;
+
+    for trak in video_children.iter().filter(|b| &b.kind == b"trak") {
+        let tkhd = find_box(video, trak.content_start, trak.end, b"tkhd")?;
+
+        max_track_id = max_track_id.max(read_u32(video, tkhd_track_id_offset(video, &tkhd))?);
+    }
+
+    // The next track id after the audio track is stored in the movie header too
+    let audio_track_id = max_track_id
+        .checked_add(1)
+        .filter(|track_id| *track_id < u32::MAX)
+        .ok_or_else(|| invalid("video track id is too large"))?;
+
+    // The audio track, with its track id changed
+    let audio_trak = audio_children
+        .iter()
+        .find(|b| &b.kind == b"trak")
+        .ok_or_else(|| invalid("audio init segment has no track"))?;
+    let audio_tkhd = find_box(audio, audio_trak.content_start, audio_trak.end, b"tkhd")?;
+    let mut audio_trak_data = audio[audio_trak.start..audio_trak.end].to_vec();
+    write_u32(
+        &mut audio_trak_data,
+        tkhd_track_id_offset(audio, &audio_tkhd) - audio_trak.start,
+        audio_track_id,
+    )?;
+
+    // The fragment defaults of the audio track, with its track id changed
+    let audio_mvex = find_box(audio, audio_moov.content_start, audio_moov.end, b"mvex")?;
+    let audio_trex = find_box(audio, audio_mvex.content_start, audio_mvex.end, b"trex")?;
+    let mut audio_trex_data = audio[audio_trex.start..audio_trex.end].to_vec();
+    write_u32(
+        &mut audio_trex_data,
+        audio_trex.content_start - audio_trex.start + 4,
+        audio_track_id,
+    )?;
+
+    let mut moov = vec![];
+
+    for child in &video_children {
+        match &child.kind {
+            b"mvhd" => {
+                // The next track id is the last field of the movie header
+                let mut mvhd = video[child.start..child.end].to_vec();
+                let len = mvhd.len();
+                write_u32(&mut mvhd, len - 4, audio_track_id + 1)?;
+                moov.extend_from_slice(&mvhd);
+            }
+            b"mvex" => {
+                let mut mvex = video[child.content_start..child.end].to_vec();
+                mvex.extend_from_slice(&audio_trex_data);
+                write_box(&mut moov, b"mvex", &mvex);
+            }
+            _ => moov.extend_from_slice(&video[child.start..child.end]),
+        }
+
+        // The audio track goes right after the last video track
+        if &child.kind == b"trak"
+            && !video_children
+                .iter()
+                .any(|b| &b.kind == b"trak" && b.start > child.start)
+        {
+            moov.extend_from_slice(&audio_trak_data);
+        }
+    }
+
+    let mut init = vec![];
+
+    for top in boxes(video, 0, video.len())? {
+        if &top.kind == b"moov" {
+            write_box(&mut init, b"moov", &moov);
+        } else {
+            init.extend_from_slice(&video[top.start..top.end]);
+        }
+    }
+
+    Ok((init, audio_track_id))
+}
+
+/// Renumbers the fragments in a media segment so that fragments of multiple streams can be
+/// interleaved in a single file.
+#[derive(Debug, Default)]
+pub struct FragmentWriter {
+    sequence_number: u32,
+}
+
+impl FragmentWriter {
+    /// Returns the `moof` and `mdat` boxes of `segment` with consecutive sequence numbers, and
+    /// with their track ids changed to `track_id` if given.
+    ///
+    /// Segment level boxes like `styp` and `sidx` are dropped, since their offsets are no longer
+    /// valid once segments are interleaved.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::InvalidMp4`] if the segment isn't a fragmented MP4 segment.
+    pub fn rewrite(&mut self, segment: &[u8], track_id: Option<u32>) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(segment.len());
+
+        for top in boxes(segment, 0, segment.len())? {
+            match &top.kind {
+                b"moof" => {
+                    let mut moof = segment[top.start..top.end].to_vec();
+                    let shift = top.start;
+
+                    self.sequence_number += 1;
+
+                    let mfhd = find_box(segment, top.content_start, top.end, b"mfhd")?;
+                    write_u32(
+                        &mut moof,
+                        mfhd.content_start + 4 - shift,
+                        self.sequence_number,
+                    )?;
+
+                    if let Some(track_id) = track_id {
+                        for traf in boxes(segment, top.content_start, top.end)?
+                            .iter()
+                            .filter(|b| &b.kind == b"traf")
+                        {
+                            let tfhd = find_box(segment, traf.content_start, traf.end, b"tfhd")?;
+                            write_u32(&mut moof, tfhd.content_start + 4 - shift, track_id)?;
+                        }
+                    }
+
+                    out.extend_from_slice(&moof);
+                }
+                b"mdat" => out.extend_from_slice(&segment[top.start..top.end]),
+                _ => {}
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Returns a box of the given `kind` with the given `contents`.
+    fn mp4_box(kind: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        write_box(&mut out, kind, contents);
+
+        out
+    }
+
+    /// Returns a version 0 full box of the given `kind` with the given `fields` after its version
+    /// and flags.
+    fn full_box(kind: &[u8; 4], fields: &[u8]) -> Vec<u8> {
+        mp4_box(kind, &[&[0; 4], fields].concat())
+    }
+
+    /// Returns a minimal fragmented MP4 init segment with a single track with the given
+    /// `track_id` and `handler`, e.g. `vide` or `soun`.
+    pub(crate) fn init_segment(track_id: u32, handler: &[u8; 4]) -> Vec<u8> {
+        let mvhd = full_box(
+            b"mvhd",
+            &[&[0; 92][..], &(track_id + 1).to_be_bytes()].concat(),
+        );
+        let tkhd = full_box(
+            b"tkhd",
+            &[&[0; 8][..], &track_id.to_be_bytes(), &[0; 68]].concat(),
+        );
+        let hdlr = full_box(b"hdlr", &[&[0; 4][..], handler, &[0; 13]].concat());
+        let trak = mp4_box(b"trak", &[tkhd, mp4_box(b"mdia", &hdlr)].concat());
+        let trex = full_box(b"trex", &[&track_id.to_be_bytes()[..], &[0; 16]].concat());
+        let moov = mp4_box(b"moov", &[mvhd, trak, mp4_box(b"mvex", &trex)].concat());
+
+        [mp4_box(b"ftyp", b"iso6\0\0\0\0iso6dash"), moov].concat()
+    }
+
+    /// Returns a media segment with a single fragment of the track with the given `track_id`,
+    /// whose samples are `payload`.
+    pub(crate) fn media_segment(sequence_number: u32, track_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mfhd = full_box(b"mfhd", &sequence_number.to_be_bytes());
+        let tfhd = full_box(b"tfhd", &track_id.to_be_bytes());
+        let moof = mp4_box(b"moof", &[mfhd, mp4_box(b"traf", &tfhd)].concat());
+
+        [
+            mp4_box(b"styp", b"msdh\0\0\0\0msdh"),
+            moof,
+            mp4_box(b"mdat", payload),
+        ]
+        .concat()
+    }
+
+    fn kinds(data: &[u8], start: usize, end: usize) -> Vec<[u8; 4]> {
+        boxes(data, start, end)
+            .unwrap()
+            .iter()
+            .map(|b| b.kind)
+            .collect()
+    }
+
+    /// Returns the track ids of the `trak` boxes in the `moov` box of `init`.
+    fn track_ids(init: &[u8]) -> Vec<u32> {
+        let moov = find_box(init, 0, init.len(), b"moov").unwrap();
+
+        boxes(init, moov.content_start, moov.end)
+            .unwrap()
+            .iter()
+            .filter(|b| &b.kind == b"trak")
+            .map(|trak| {
+                let tkhd = find_box(init, trak.content_start, trak.end, b"tkhd").unwrap();
+
+                read_u32(init, tkhd_track_id_offset(init, &tkhd)).unwrap()
+            })
+            .collect()
+    }
+
+    /// Returns the sequence number and track id of every fragment in `data`.
+    fn fragments(data: &[u8]) -> Vec<(u32, u32)> {
+        boxes(data, 0, data.len())
+            .unwrap()
+            .iter()
+            .filter(|b| &b.kind == b"moof")
+            .map(|moof| {
+                let mfhd = find_box(data, moof.content_start, moof.end, b"mfhd").unwrap();
+                let traf = find_box(data, moof.content_start, moof.end, b"traf").unwrap();
+                let tfhd = find_box(data, traf.content_start, traf.end, b"tfhd").unwrap();
+
+                (
+                    read_u32(data, mfhd.content_start + 4).unwrap(),
+                    read_u32(data, tfhd.content_start + 4).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_init_segments() {
+        let video = init_segment(1, b"vide");
+        let audio = init_segment(1, b"soun");
+
+        let (init, audio_track_id) = merge_init_segments(&video, &audio).unwrap();
+
+        assert_eq!(audio_track_id, 2);
+        assert_eq!(kinds(&init, 0, init.len()), [*b"ftyp", *b"moov"]);
+
+        let moov = find_box(&init, 0, init.len(), b"moov").unwrap();
+        assert_eq!(
+            kinds(&init, moov.content_start, moov.end),
+            [*b"mvhd", *b"trak", *b"trak", *b"mvex"]
+        );
+        assert_eq!(track_ids(&init), [1, 2]);
+
+        // The audio track is copied as it is apart from its track id
+        let audio_trak = boxes(&init, moov.content_start, moov.end).unwrap()[2];
+        assert!(init[audio_trak.start..audio_trak.end]
+            .windows(4)
+            .any(|window| window == b"soun"));
+
+        // The next track id comes after the audio track
+        let mvhd = find_box(&init, moov.content_start, moov.end, b"mvhd").unwrap();
+        assert_eq!(read_u32(&init, mvhd.end - 4).unwrap(), 3);
+
+        // Both tracks have fragment defaults
+        let mvex = find_box(&init, moov.content_start, moov.end, b"mvex").unwrap();
+        let trex_track_ids: Vec<u32> = boxes(&init, mvex.content_start, mvex.end)
+            .unwrap()
+            .iter()
+            .map(|trex| read_u32(&init, trex.content_start + 4).unwrap())
+            .collect();
+        assert_eq!(trex_track_ids, [1, 2]);
+    }
+
+    #[test]
+    fn rejects_init_segment_without_movie() {
+        let video = init_segment(1, b"vide");
+        let audio = mp4_box(b"ftyp", b"iso6\0\0\0\0iso6dash");
+
+        let err = merge_init_segments(&video, &audio).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            ErrorKind::InvalidMp4("missing `moov` box".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn rewrites_fragments() {
+        let mut writer = FragmentWriter::default();
+
+        let video = writer
+            .rewrite(&media_segment(7, 1, b"video"), None)
+            .unwrap();
+        let audio = writer
+            .rewrite(&media_segment(7, 1, b"audio"), Some(2))
+            .unwrap();
+        let out = [video, audio].concat();
+
+        // Segment level boxes are dropped, and fragments are numbered consecutively
+        assert_eq!(
+            kinds(&out, 0, out.len()),
+            [*b"moof", *b"mdat", *b"moof", *b"mdat"]
+        );
+        assert_eq!(fragments(&out), [(1, 1), (2, 2)]);
+
+        let payloads: Vec<&[u8]> = boxes(&out, 0, out.len())
+            .unwrap()
+            .iter()
+            .filter(|b| &b.kind == b"mdat")
+            .map(|mdat| &out[mdat.content_start..mdat.end])
+            .collect();
+        assert_eq!(payloads, [&b"video"[..], &b"audio"[..]]);
+    }
+
+    #[test]
+    fn rejects_oversized_boxes() {
+        let exceeds_parent = [&1u32.to_be_bytes()[..], b"mdat", &[0, 0, 0, 0, 0, 0, 1, 0]].concat();
+        let overflows = [&1u32.to_be_bytes()[..], b"mdat", &u64::MAX.to_be_bytes()].concat();
+        let expected = ErrorKind::InvalidMp4("box exceeds its parent".to_string()).to_string();
+
+        for data in [exceeds_parent, overflows] {
+            let err = FragmentWriter::default().rewrite(&data, None).unwrap_err();
+
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{instrument, trace};
+
+use crate::database::Film;
+use crate::error::Error;
+
+/// The country whose rating system the age restrictions of films follow.
+const RATING_COUNTRY: &str = "DK";
+
+/// Escapes the characters that can't appear verbatim in XML text.
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Maps an age restriction, e.g. `15` or `A`, to a certification in the `country:rating` format
+/// that Kodi and Jellyfin expect in the `mpaa` element.
+fn certification(age_restriction: &str) -> Option<String> {
+    let age_restriction = age_restriction.trim();
+
+    if age_restriction.is_empty() {
+        return None;
+    }
+
+    let rating = if age_restriction.eq_ignore_ascii_case("a")
+        || age_restriction.to_lowercase().contains("alle")
+    {
+        "A"
+    } else {
+        age_restriction.trim_end_matches('+')
+    };
+
+    Some(format!("{}:{}", RATING_COUNTRY, rating))
+}
+
+/// Returns the path of the sidecar file for the video at `video_path`.
+pub fn sidecar_path(video_path: &Path) -> PathBuf {
+    video_path.with_extension("nfo")
+}
+
+/// Renders the details of `film` as a Kodi movie `.nfo` document.
+pub fn render(film: &Film) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    xml.push_str("<movie>\n");
+
+    let mut element = |name: &str, value: &str| {
+        let _ = writeln!(xml, "  <{0}>{1}</{0}>", name, xml_escape(value));
+    };
+
+    if let Some(title) = &film.title {
+        element("title", title);
+    }
+
+    if let Some(original_title) = film.original_title.as_ref().or(film.title.as_ref()) {
+        element("originaltitle", original_title);
+    }
+
+    if let Some(director) = &film.director {
+        element("director", director);
+    }
+
+    if let Some(year) = film.production_year {
+        element("year", &year.to_string());
+    }
+
+    if let Some(duration) = film.duration {
+        element("runtime", &duration.to_string());
+    }
+
+    if let Some(description) = &film.description {
+        element("plot", description);
+    }
+
+    for genre in &film.genres {
+        element("genre", &genre.title);
+    }
+
+    for country in &film.countries {
+        element("country", &country.title);
+    }
+
+    if let Some(certification) = film.age_restriction.as_deref().and_then(certification) {
+        element("mpaa", &certification);
+    }
+
+    let _ = writeln!(
+        xml,
+        "  <uniqueid type=\"offstream\" default=\"true\">{}</uniqueid>",
+        film.id
+    );
+    xml.push_str("</movie>\n");
+
+    xml
+}
+
+/// Writes a `.nfo` sidecar with the details of `film` next to the video at `video_path`.
+#[instrument(err, skip(film), fields(film_id = film.id))]
+pub fn write_sidecar(film: &Film, video_path: &Path) -> Result<(), Error> {
+    let path = sidecar_path(video_path);
+
+    trace!(?path, "Writing .nfo sidecar");
+
+    fs::write(path, render(film))?;
+
+    Ok(())
+}
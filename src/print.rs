@@ -0,0 +1,283 @@
+use chrono::{DateTime, Utc};
+
+use crate::database::{
+    DownloadAttempt, Film, FilmHistoryEntry, FilmSearchResult, MissingFilmDownload,
+};
+use crate::import::ImportSummary;
+
+/// The placeholder printed for missing values.
+const NONE: &str = "(none)";
+
+/// Formats a timestamp for display.
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Prints a table of `films`, one film per line.
+pub fn film_list(films: &[Film]) {
+    println!(
+        "{:>8} {:>4} {:<10} {:<8} TITLE",
+        "ID", "YEAR", "DOWNLOAD", "LISTED"
+    );
+
+    for film in films {
+        let download = match &film.download {
+            Some(download) if download.finished_at.is_some() => "done",
+            Some(_) => "started",
+            None => "missing",
+        };
+        let listed = if film.delisted_at.is_some() {
+            "delisted"
+        } else {
+            "listed"
+        };
+        let production_year = film
+            .production_year
+            .map_or_else(String::new, |year| year.to_string());
+
+        println!(
+            "{:>8} {:>4} {:<10} {:<8} {} - {}",
+            film.id,
+            production_year,
+            download,
+            listed,
+            film.director.as_deref().unwrap_or(NONE),
+            film.title.as_deref().unwrap_or(NONE)
+        );
+    }
+}
+
+/// Prints everything stored about `film`.
+pub fn film_details(film: &Film) {
+    fn field(name: &str, value: Option<String>) {
+        println!("{:<20} {}", name, value.as_deref().unwrap_or(NONE));
+    }
+
+    fn list(name: &str, values: Vec<String>) {
+        let value = if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        };
+
+        field(name, value);
+    }
+
+    field("Id", Some(film.id.to_string()));
+    field("Title", film.title.clone());
+    field("Original title", film.original_title.clone());
+    field("Director", film.director.clone());
+    field(
+        "Production year",
+        film.production_year.map(|year| year.to_string()),
+    );
+    field(
+        "Duration",
+        film.duration.map(|duration| duration.to_string()),
+    );
+    field("Age restriction", film.age_restriction.clone());
+    field(
+        "Festival year",
+        film.year.as_ref().and_then(|year| year.title.clone()),
+    );
+    list(
+        "Genres",
+        film.genres
+            .iter()
+            .map(|genre| genre.title.clone())
+            .collect(),
+    );
+    list(
+        "Countries",
+        film.countries
+            .iter()
+            .map(|country| format!("{} ({})", country.title, country.code))
+            .collect(),
+    );
+    list("Competitions", film.competitions.clone());
+    field("Status", film.status.clone());
+    field("Vimeo id", film.vimeo_id.clone());
+    field("Greeting vimeo id", film.greeting_vimeo_id.clone());
+    field("Fetched at", film.fetched_at.as_ref().map(format_time));
+    field("Listed at", film.listed_at.as_ref().map(format_time));
+    field("Last seen at", film.last_seen_at.as_ref().map(format_time));
+    field("Delisted at", film.delisted_at.as_ref().map(format_time));
+
+    match &film.download {
+        Some(download) => {
+            field("Download path", Some(download.path.clone()));
+            field("Download started", Some(format_time(&download.started_at)));
+            field(
+                "Download finished",
+                download.finished_at.as_ref().map(format_time),
+            );
+        }
+        None => field("Download path", None),
+    }
+
+    // Only films with a greeting can have a greeting download
+    if film.greeting_vimeo_id.is_some() || film.greeting_download.is_some() {
+        match &film.greeting_download {
+            Some(download) => {
+                field("Greeting path", Some(download.path.clone()));
+                field("Greeting started", Some(format_time(&download.started_at)));
+                field(
+                    "Greeting finished",
+                    download.finished_at.as_ref().map(format_time),
+                );
+            }
+            None => field("Greeting path", None),
+        }
+    }
+
+    list(
+        "Thumbnails",
+        film.thumbnails
+            .iter()
+            .map(|(resolution, url)| format!("{}: {}", resolution, url))
+            .collect(),
+    );
+
+    if let Some(description) = &film.description {
+        println!();
+        println!("{}", description);
+    }
+}
+
+/// Prints the download attempts of a film, oldest first, with the stderr of the last failed
+/// attempt.
+pub fn download_attempts(attempts: &[DownloadAttempt]) {
+    if attempts.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("Download attempts:");
+
+    for attempt in attempts {
+        let outcome = match (&attempt.finished_at, &attempt.failure) {
+            (None, _) => "interrupted".to_string(),
+            (Some(_), None) => "succeeded".to_string(),
+            (Some(_), Some(failure)) => {
+                format!("failed ({}): {}", failure.category.as_str(), failure.error)
+            }
+        };
+
+        println!(
+            "  {} {:<8} {:<10} {}",
+            format_time(&attempt.started_at),
+            attempt.kind.as_str(),
+            attempt.downloader,
+            outcome
+        );
+    }
+
+    let stderr_tail = attempts
+        .last()
+        .and_then(|attempt| attempt.failure.as_ref())
+        .and_then(|failure| failure.stderr_tail.as_ref());
+
+    if let Some(stderr_tail) = stderr_tail {
+        println!();
+        println!("{}", stderr_tail);
+    }
+}
+
+/// Prints the results of a film search, best match first.
+pub fn search_results(results: &[FilmSearchResult]) {
+    if results.is_empty() {
+        println!("No films matched the search");
+    }
+
+    // bm25 ranks better matches with lower, negative numbers whose magnitude depends on the size
+    // of the corpus, so matches are scored relative to the best one
+    let best_rank = results.iter().map(|result| result.rank).fold(0.0, f64::min);
+
+    for result in results {
+        let production_year = result
+            .production_year
+            .map_or_else(String::new, |year| format!(" ({})", year));
+        let relevance = if best_rank < 0.0 {
+            format!(" [relevance {:.0}%]", result.rank / best_rank * 100.0)
+        } else {
+            String::new()
+        };
+
+        println!(
+            "{:>8} {} - {}{}{}",
+            result.film_id,
+            result.director.as_deref().unwrap_or(NONE),
+            result.title.as_deref().unwrap_or(NONE),
+            production_year,
+            relevance
+        );
+        println!("{:>8} {}", "", result.snippet.replace('\n', " "));
+    }
+}
+
+/// Prints the recorded changes to the details of the film with the given `film_id`.
+pub fn film_history(film_id: u64, history: &[FilmHistoryEntry]) {
+    if history.is_empty() {
+        println!("No changes recorded for film {}", film_id);
+    }
+
+    for entry in history {
+        println!(
+            "{} {:<20} {} -> {}",
+            format_time(&entry.changed_at),
+            entry.field,
+            entry.old_value.as_deref().unwrap_or(NONE),
+            entry.new_value.as_deref().unwrap_or(NONE)
+        );
+    }
+}
+
+/// Prints the films whose downloads were named using fallbacks, along with the details they're
+/// missing.
+pub fn incomplete_films(downloads: &[&MissingFilmDownload]) {
+    if downloads.is_empty() {
+        return;
+    }
+
+    println!(
+        "{} films are missing details and were named using fallbacks:",
+        downloads.len()
+    );
+
+    for download in downloads {
+        println!(
+            "{:>8} {}",
+            download.id,
+            download.missing_fields().join(", ")
+        );
+    }
+}
+
+/// Prints what happened to the records of an import.
+pub fn import_summary(summary: &ImportSummary, overwrite: bool, dry_run: bool) {
+    for conflict in &summary.conflicts {
+        println!(
+            "{} film {}: {}",
+            match (overwrite, dry_run) {
+                (true, true) => "Would overwrite",
+                (true, false) => "Overwrote",
+                (false, _) => "Skipped",
+            },
+            conflict.film_id,
+            conflict.fields.join(", ")
+        );
+    }
+
+    println!(
+        "{} new, {} updated, {} unchanged, {} conflicts{}",
+        summary.new,
+        summary.updated,
+        summary.unchanged,
+        summary.conflicts.len(),
+        if dry_run {
+            " (dry run, nothing was saved)"
+        } else {
+            ""
+        }
+    );
+}
@@ -0,0 +1,374 @@
+use std::sync::Mutex;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tracing::field::display;
+use tracing::{trace, Span};
+
+/// The progress of a download, as reported by a downloader.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Progress {
+    /// How much of the download is done, from 0 to 100.
+    pub percent: f64,
+    /// The total size of the download, e.g. `10.00MiB`, prefixed with `~` if it's an estimate.
+    pub total_size: Option<String>,
+    /// The download speed, e.g. `1.00MiB/s`.
+    pub speed: Option<String>,
+    /// The estimated time left, e.g. `00:05`.
+    pub eta: Option<String>,
+    /// The number of the fragment being downloaded and the total number of fragments.
+    pub fragment: Option<(u64, u64)>,
+}
+
+impl Progress {
+    /// Parses a progress line printed by youtube-dl or yt-dlp, e.g.
+    /// `[download]  50.0% of ~10.00MiB at 1.00MiB/s ETA 00:05 (frag 3/10)`.
+    ///
+    /// Returns `None` if the line doesn't report progress.
+    pub fn parse(line: &str) -> Option<Progress> {
+        let line = line.trim().strip_prefix("[download]")?;
+        let mut tokens = line.split_whitespace();
+        let percent = tokens.next()?.strip_suffix('%')?.parse().ok()?;
+        let mut progress = Progress {
+            percent,
+            ..Default::default()
+        };
+
+        // Speeds and ETAs that can't be determined are reported as e.g. `Unknown speed`
+        let known =
+            |token: Option<&str>| token.filter(|token| *token != "Unknown").map(String::from);
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "of" => {
+                    // yt-dlp pads estimated sizes, as in `~  10.00MiB`
+                    progress.total_size = match tokens.next() {
+                        Some("~") => tokens.next().map(|size| format!("~{}", size)),
+                        size => size.map(String::from),
+                    }
+                }
+                "at" => progress.speed = known(tokens.next()),
+                "ETA" => progress.eta = known(tokens.next()),
+                "(frag" => {
+                    progress.fragment = tokens.next().and_then(|fragment| {
+                        let (index, count) = fragment.trim_end_matches(')').split_once('/')?;
+
+                        Some((index.parse().ok()?, count.parse().ok()?))
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Some(progress)
+    }
+}
+
+/// Parses the number of formats yt-dlp is about to download one after the other from a line
+/// like `[info] 123: Downloading 1 format(s): 137+140`.
+pub fn parse_num_passes(line: &str) -> Option<u64> {
+    let line = line.trim().strip_prefix("[info]")?;
+    let (_, formats) = line.split_once("format(s):")?;
+
+    Some(formats.trim().split('+').count() as u64)
+}
+
+/// Returns whether `line` announces the file the next pass of a download is written to, as in
+/// `[download] Destination: film.f137.mp4`.
+pub fn is_destination(line: &str) -> bool {
+    line.trim()
+        .strip_prefix("[download]")
+        .is_some_and(|line| line.trim_start().starts_with("Destination:"))
+}
+
+/// The passes of a download, such as one for the video and one for the audio, each of which is
+/// reported from 0 to 100%.
+#[derive(Debug, Clone, Copy)]
+struct Passes {
+    expected: u64,
+    started: u64,
+}
+
+impl Passes {
+    /// Returns how much of the whole download is done when the current pass is `percent` done.
+    fn overall_percent(&self, percent: f64) -> f64 {
+        let expected = self.expected.max(self.started).max(1);
+        let done = self.started.saturating_sub(1);
+
+        (done as f64 * 100.0 + percent.clamp(0.0, 100.0)) / expected as f64
+    }
+}
+
+/// The progress bars of concurrent downloads, drawn to stderr when it's an interactive terminal.
+#[derive(Debug)]
+pub struct ProgressBars {
+    multi: MultiProgress,
+}
+
+impl ProgressBars {
+    pub fn new() -> ProgressBars {
+        ProgressBars {
+            multi: MultiProgress::new(),
+        }
+    }
+
+    /// Adds a progress bar for a download named `name`, which records its progress in the
+    /// current span as well.
+    pub fn add(&self, name: &str) -> DownloadProgress {
+        let style = ProgressStyle::with_template("{prefix} [{bar:30}] {percent:>3}% {msg}")
+            .expect("progress bar template is valid")
+            .progress_chars("=> ");
+        let bar = self.multi.add(ProgressBar::new(100));
+        bar.set_style(style);
+        bar.set_prefix(name.to_string());
+
+        DownloadProgress {
+            bar,
+            span: Span::current(),
+            last: Mutex::new(None),
+            passes: Mutex::new(Passes {
+                expected: 1,
+                started: 0,
+            }),
+        }
+    }
+}
+
+/// Reports the progress of a single download.
+///
+/// Every update is drawn as a progress bar and emitted as a trace event. The last update is
+/// recorded as the `percent`, `speed`, `eta`, `fragment_index` and `fragment_count` fields of the
+/// span the reporter was created in once the download ends, since recording every update would
+/// repeat the fields in each event of the span.
+///
+/// Downloads made in several passes are drawn as a single bar, split evenly between the passes.
+#[derive(Debug)]
+pub struct DownloadProgress {
+    bar: ProgressBar,
+    span: Span,
+    last: Mutex<Option<Progress>>,
+    passes: Mutex<Passes>,
+}
+
+impl DownloadProgress {
+    /// Sets the number of passes the download is expected to take.
+    pub fn expect_passes(&self, passes: u64) {
+        self.passes
+            .lock()
+            .expect("progress lock is poisoned")
+            .expected = passes;
+    }
+
+    /// Marks the start of the next pass of the download.
+    pub fn start_pass(&self) {
+        self.passes
+            .lock()
+            .expect("progress lock is poisoned")
+            .started += 1;
+    }
+
+    pub fn update(&self, progress: &Progress) {
+        let passes = *self.passes.lock().expect("progress lock is poisoned");
+
+        trace!(
+            parent: &self.span,
+            percent = %format!("{:.1}", progress.percent),
+            pass = passes.started,
+            total_size = ?progress.total_size,
+            speed = ?progress.speed,
+            eta = ?progress.eta,
+            fragment = ?progress.fragment,
+            "Download progress"
+        );
+
+        let mut msg = vec![];
+
+        if let Some(total_size) = &progress.total_size {
+            msg.push(format!("of {}", total_size));
+        }
+
+        if let Some(speed) = &progress.speed {
+            msg.push(format!("at {}", speed));
+        }
+
+        if let Some(eta) = &progress.eta {
+            msg.push(format!("ETA {}", eta));
+        }
+
+        if let Some((index, count)) = progress.fragment {
+            msg.push(format!("(frag {}/{})", index, count));
+        }
+
+        // Passes that take longer than expected would otherwise move the bar backwards
+        let position = passes.overall_percent(progress.percent) as u64;
+        self.bar.set_position(position.max(self.bar.position()));
+        self.bar.set_message(msg.join(" "));
+
+        *self.last.lock().expect("progress lock is poisoned") = Some(progress.clone());
+    }
+
+    /// Records the last update as fields of the span.
+    fn record_last(&self) {
+        let last = self.last.lock().expect("progress lock is poisoned");
+        let progress = match last.as_ref() {
+            Some(progress) => progress,
+            None => return,
+        };
+
+        self.span
+            .record("percent", &display(format!("{:.1}", progress.percent)));
+
+        if let Some(speed) = &progress.speed {
+            self.span.record("speed", &speed.as_str());
+        }
+
+        if let Some(eta) = &progress.eta {
+            self.span.record("eta", &eta.as_str());
+        }
+
+        if let Some((index, count)) = progress.fragment {
+            self.span.record("fragment_index", &index);
+            self.span.record("fragment_count", &count);
+        }
+    }
+
+    /// Marks the download as finished.
+    pub fn finish(&self) {
+        self.record_last();
+        self.bar.set_position(100);
+        self.bar.finish_with_message("done");
+    }
+
+    /// Marks the download as failed, leaving the bar where it stopped.
+    pub fn fail(&self) {
+        self.record_last();
+        self.bar.abandon_with_message("failed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(
+        percent: f64,
+        total_size: Option<&str>,
+        speed: Option<&str>,
+        eta: Option<&str>,
+        fragment: Option<(u64, u64)>,
+    ) -> Progress {
+        Progress {
+            percent,
+            total_size: total_size.map(String::from),
+            speed: speed.map(String::from),
+            eta: eta.map(String::from),
+            fragment,
+        }
+    }
+
+    #[test]
+    fn parses_progress_lines() {
+        let lines = [
+            (
+                "[download]  42.3% of 1.2GiB at 3.4MiB/s ETA 00:12",
+                progress(42.3, Some("1.2GiB"), Some("3.4MiB/s"), Some("00:12"), None),
+            ),
+            (
+                "[download]  10.0% of ~  50.00MiB at  1.00MiB/s ETA 00:40 (frag 3/120)",
+                progress(
+                    10.0,
+                    Some("~50.00MiB"),
+                    Some("1.00MiB/s"),
+                    Some("00:40"),
+                    Some((3, 120)),
+                ),
+            ),
+            (
+                "[download]  10.0% of ~50.00MiB at 1.00MiB/s ETA 00:40",
+                progress(
+                    10.0,
+                    Some("~50.00MiB"),
+                    Some("1.00MiB/s"),
+                    Some("00:40"),
+                    None,
+                ),
+            ),
+            (
+                "[download]   0.0% of 1.2GiB at Unknown speed ETA Unknown ETA",
+                progress(0.0, Some("1.2GiB"), None, None, None),
+            ),
+            (
+                "[download] 100% of 1.2GiB in 00:00:35 at 3.4MiB/s",
+                progress(100.0, Some("1.2GiB"), Some("3.4MiB/s"), None, None),
+            ),
+            (
+                "[download] 100% of 1.2GiB in 00:35",
+                progress(100.0, Some("1.2GiB"), None, None, None),
+            ),
+        ];
+
+        for (line, expected) in lines {
+            assert_eq!(Progress::parse(line), Some(expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn ignores_other_lines() {
+        let lines = [
+            "[download] Destination: film.f137.mp4",
+            "[download] film.mp4 has already been downloaded",
+            "[Merger] Merging formats into \"film.mp4\"",
+            "[info] 123: Downloading 1 format(s): 137+140",
+            "",
+        ];
+
+        for line in lines {
+            assert_eq!(Progress::parse(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn parses_passes() {
+        assert_eq!(
+            parse_num_passes("[info] 123: Downloading 1 format(s): 137+140"),
+            Some(2)
+        );
+        assert_eq!(
+            parse_num_passes("[info] 123: Downloading 1 format(s): 18"),
+            Some(1)
+        );
+        assert_eq!(parse_num_passes("[info] Writing video metadata"), None);
+
+        assert!(is_destination("[download] Destination: film.f137.mp4"));
+        assert!(!is_destination("[download]  42.3% of 1.2GiB"));
+        assert!(!is_destination(
+            "[Merger] Merging formats into \"film.mp4\""
+        ));
+    }
+
+    #[test]
+    fn splits_bar_between_passes() {
+        let bars = ProgressBars::new();
+        let download = bars.add("film.mp4");
+        let update = |percent| download.update(&progress(percent, None, None, None, None));
+
+        download.expect_passes(2);
+        download.start_pass();
+        update(50.0);
+        assert_eq!(download.bar.position(), 25);
+        update(100.0);
+        assert_eq!(download.bar.position(), 50);
+
+        download.start_pass();
+        update(0.0);
+        assert_eq!(download.bar.position(), 50);
+        update(50.0);
+        assert_eq!(download.bar.position(), 75);
+
+        // An unexpected third pass doesn't move the bar backwards
+        download.start_pass();
+        update(10.0);
+        assert_eq!(download.bar.position(), 75);
+        update(100.0);
+        assert_eq!(download.bar.position(), 100);
+    }
+}
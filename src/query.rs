@@ -0,0 +1,376 @@
+use clap::ArgEnum;
+use rusqlite::ToSql;
+
+/// The download state of a film.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadState {
+    /// The film has never been downloaded.
+    Missing,
+    /// A download of the film was started, but never finished.
+    Started,
+    /// The film has been downloaded.
+    Finished,
+}
+
+/// The field to sort films by.
+#[derive(ArgEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FilmSort {
+    #[default]
+    Id,
+    Title,
+    Director,
+    ProductionYear,
+    Duration,
+    FetchedAt,
+}
+
+/// Builder for a query for films stored in the database, which is run by
+/// [`Database::query_films`](crate::database::Database::query_films).
+///
+/// Every filter that is set must match for a film to be included.
+#[derive(Debug, Default)]
+pub struct FilmQuery {
+    production_year: Option<u64>,
+    festival_year: Option<String>,
+    genre: Option<String>,
+    country: Option<String>,
+    competition: Option<String>,
+    download_state: Option<DownloadState>,
+    min_duration: Option<u64>,
+    max_duration: Option<u64>,
+    age_restriction: Option<String>,
+    delisted: Option<bool>,
+    sort: FilmSort,
+    descending: bool,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+impl FilmQuery {
+    /// Returns a new query that matches all films, ordered by id.
+    pub fn new() -> FilmQuery {
+        FilmQuery::default()
+    }
+
+    /// Only matches films with the given production year.
+    pub fn production_year(mut self, year: u64) -> Self {
+        self.production_year = Some(year);
+        self
+    }
+
+    /// Only matches films shown at the festival year with the given title, e.g. `2021`.
+    pub fn festival_year<S: Into<String>>(mut self, title: S) -> Self {
+        self.festival_year = Some(title.into());
+        self
+    }
+
+    /// Only matches films with the genre with the given identifier.
+    pub fn genre<S: Into<String>>(mut self, identifier: S) -> Self {
+        self.genre = Some(identifier.into());
+        self
+    }
+
+    /// Only matches films from the country with the given code, ignoring case.
+    pub fn country<S: Into<String>>(mut self, code: S) -> Self {
+        self.country = Some(code.into());
+        self
+    }
+
+    /// Only matches films in the competition with the given name, ignoring case.
+    pub fn competition<S: Into<String>>(mut self, name: S) -> Self {
+        self.competition = Some(name.into());
+        self
+    }
+
+    /// Only matches films with the given download state.
+    pub fn download_state(mut self, state: DownloadState) -> Self {
+        self.download_state = Some(state);
+        self
+    }
+
+    /// Only matches films with a duration of at least `duration`.
+    pub fn min_duration(mut self, duration: u64) -> Self {
+        self.min_duration = Some(duration);
+        self
+    }
+
+    /// Only matches films with a duration of at most `duration`.
+    pub fn max_duration(mut self, duration: u64) -> Self {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    /// Only matches films with the given age restriction.
+    pub fn age_restriction<S: Into<String>>(mut self, age_restriction: S) -> Self {
+        self.age_restriction = Some(age_restriction.into());
+        self
+    }
+
+    /// Only matches films that are delisted if `delisted` is true, or still listed otherwise.
+    pub fn delisted(mut self, delisted: bool) -> Self {
+        self.delisted = Some(delisted);
+        self
+    }
+
+    /// Sorts the films by `sort`, in descending order if `descending` is set.
+    pub fn sort(mut self, sort: FilmSort, descending: bool) -> Self {
+        self.sort = sort;
+        self.descending = descending;
+        self
+    }
+
+    /// Returns at most `limit` films.
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skips the first `offset` films.
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Returns the `WHERE`, `ORDER BY`, `LIMIT` and `OFFSET` clauses of the query along with
+    /// their parameters, to be appended to a statement selecting from `films AS f`, joined with
+    /// `film_years AS y`, `film_listings AS l` and `film_downloads AS dl`.
+    pub fn to_sql(&self) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut conditions: Vec<&str> = vec![];
+        let mut params: Vec<Box<dyn ToSql>> = vec![];
+
+        if let Some(year) = self.production_year {
+            conditions.push("f.production_year = ?");
+            params.push(Box::new(year));
+        }
+
+        if let Some(title) = &self.festival_year {
+            conditions.push("y.title = ?");
+            params.push(Box::new(title.clone()));
+        }
+
+        if let Some(identifier) = &self.genre {
+            conditions.push(
+                "EXISTS (
+                    SELECT 1 FROM film_genres AS fg
+                    INNER JOIN genres AS g ON g.id = fg.genre_id
+                    WHERE fg.film_id = f.id AND g.identifier = ?
+                )",
+            );
+            params.push(Box::new(identifier.clone()));
+        }
+
+        if let Some(code) = &self.country {
+            conditions.push(
+                "EXISTS (
+                    SELECT 1 FROM film_countries AS fc
+                    INNER JOIN countries AS c ON c.id = fc.country_id
+                    WHERE fc.film_id = f.id AND c.code = ? COLLATE NOCASE
+                )",
+            );
+            params.push(Box::new(code.clone()));
+        }
+
+        if let Some(name) = &self.competition {
+            conditions.push(
+                "EXISTS (
+                    SELECT 1 FROM film_competitions AS fc
+                    WHERE fc.film_id = f.id AND fc.name = ? COLLATE NOCASE
+                )",
+            );
+            params.push(Box::new(name.clone()));
+        }
+
+        match self.download_state {
+            Some(DownloadState::Missing) => conditions.push("dl.id IS NULL"),
+            Some(DownloadState::Started) => {
+                conditions.push("dl.id IS NOT NULL AND dl.finished_at IS NULL")
+            }
+            Some(DownloadState::Finished) => conditions.push("dl.finished_at IS NOT NULL"),
+            None => {}
+        }
+
+        if let Some(duration) = self.min_duration {
+            conditions.push("f.duration >= ?");
+            params.push(Box::new(duration));
+        }
+
+        if let Some(duration) = self.max_duration {
+            conditions.push("f.duration <= ?");
+            params.push(Box::new(duration));
+        }
+
+        if let Some(age_restriction) = &self.age_restriction {
+            conditions.push("f.age_restriction = ?");
+            params.push(Box::new(age_restriction.clone()));
+        }
+
+        match self.delisted {
+            Some(true) => conditions.push("l.delisted_at IS NOT NULL"),
+            Some(false) => conditions.push("l.delisted_at IS NULL"),
+            None => {}
+        }
+
+        let mut sql = String::new();
+
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+
+        let sort_column = match self.sort {
+            FilmSort::Id => "f.id",
+            FilmSort::Title => "f.title COLLATE NOCASE",
+            FilmSort::Director => "f.director COLLATE NOCASE",
+            FilmSort::ProductionYear => "f.production_year",
+            FilmSort::Duration => "f.duration",
+            FilmSort::FetchedAt => "f.fetched_at",
+        };
+        let direction = if self.descending { "DESC" } else { "ASC" };
+
+        sql.push_str(&format!(
+            " ORDER BY {} {}, f.id {}",
+            sort_column, direction, direction
+        ));
+
+        // SQLite only accepts an OFFSET along with a LIMIT, where a negative LIMIT means no limit
+        if self.limit.is_some() || self.offset.is_some() {
+            sql.push_str(" LIMIT ? OFFSET ?");
+            params.push(Box::new(self.limit.map_or(-1, i64::from)));
+            params.push(Box::new(self.offset.unwrap_or(0)));
+        }
+
+        (sql, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::types::{ToSqlOutput, Value};
+
+    use super::*;
+
+    fn values(params: &[Box<dyn ToSql>]) -> Vec<Value> {
+        params
+            .iter()
+            .map(|param| match param.to_sql().unwrap() {
+                ToSqlOutput::Borrowed(value) => value.into(),
+                ToSqlOutput::Owned(value) => value,
+                output => panic!("unexpected parameter {:?}", output),
+            })
+            .collect()
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_owned())
+    }
+
+    #[test]
+    fn matches_all_films_by_default() {
+        let (sql, params) = FilmQuery::new().to_sql();
+
+        assert_eq!(sql, " ORDER BY f.id ASC, f.id ASC");
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn combines_filters_in_order() {
+        let (sql, params) = FilmQuery::new()
+            .production_year(2020)
+            .festival_year("2021")
+            .genre("drama")
+            .country("dk")
+            .competition("Danish")
+            .download_state(DownloadState::Finished)
+            .min_duration(60)
+            .max_duration(120)
+            .age_restriction("15")
+            .delisted(false)
+            .to_sql();
+
+        let conditions = [
+            "f.production_year = ?",
+            "y.title = ?",
+            "g.identifier = ?",
+            "c.code = ? COLLATE NOCASE",
+            "fc.name = ? COLLATE NOCASE",
+            "dl.finished_at IS NOT NULL",
+            "f.duration >= ?",
+            "f.duration <= ?",
+            "f.age_restriction = ?",
+            "l.delisted_at IS NULL",
+        ];
+        let positions: Vec<usize> = conditions
+            .iter()
+            .map(|condition| {
+                sql.find(condition)
+                    .unwrap_or_else(|| panic!("`{}` is missing from `{}`", condition, sql))
+            })
+            .collect();
+
+        assert!(sql.starts_with(" WHERE f.production_year = ? AND y.title = ? AND EXISTS"));
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(sql.ends_with(" AND l.delisted_at IS NULL ORDER BY f.id ASC, f.id ASC"));
+        assert_eq!(
+            values(&params),
+            vec![
+                Value::Integer(2020),
+                text("2021"),
+                text("drama"),
+                text("dk"),
+                text("Danish"),
+                Value::Integer(60),
+                Value::Integer(120),
+                text("15"),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_download_state() {
+        let states = [
+            (DownloadState::Missing, " WHERE dl.id IS NULL"),
+            (
+                DownloadState::Started,
+                " WHERE dl.id IS NOT NULL AND dl.finished_at IS NULL",
+            ),
+            (DownloadState::Finished, " WHERE dl.finished_at IS NOT NULL"),
+        ];
+
+        for (state, clause) in states {
+            let (sql, params) = FilmQuery::new().download_state(state).to_sql();
+
+            assert_eq!(sql, format!("{} ORDER BY f.id ASC, f.id ASC", clause));
+            assert!(params.is_empty());
+        }
+
+        let (sql, _) = FilmQuery::new().delisted(true).to_sql();
+        assert!(sql.starts_with(" WHERE l.delisted_at IS NOT NULL ORDER BY"));
+    }
+
+    #[test]
+    fn sorts_and_paginates() {
+        let (sql, params) = FilmQuery::new()
+            .sort(FilmSort::Title, true)
+            .limit(10)
+            .offset(20)
+            .to_sql();
+
+        assert_eq!(
+            sql,
+            " ORDER BY f.title COLLATE NOCASE DESC, f.id DESC LIMIT ? OFFSET ?"
+        );
+        assert_eq!(
+            values(&params),
+            vec![Value::Integer(10), Value::Integer(20)]
+        );
+
+        // An offset without a limit needs a negative limit
+        let (sql, params) = FilmQuery::new().production_year(2020).offset(5).to_sql();
+
+        assert!(sql.ends_with(" LIMIT ? OFFSET ?"));
+        assert_eq!(
+            values(&params),
+            vec![Value::Integer(2020), Value::Integer(-1), Value::Integer(5)]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::{sleep, Instant};
+use tracing::{debug, trace};
+
+use crate::error::{Error, ErrorKind};
+
+/// The factor the rate is divided by when the API pushes back.
+const BACKOFF_FACTOR: f64 = 2.0;
+/// The fraction of the configured rate that is regained for each successful request.
+const RECOVERY_FRACTION: f64 = 0.1;
+/// The lowest fraction of the configured rate the limiter will slow down to.
+const MIN_RATE_FRACTION: f64 = 1.0 / 16.0;
+
+/// Token bucket rate limiter that adapts its rate when the server pushes back.
+///
+/// The bucket holds up to `burst` tokens and is refilled at the current rate. Every request
+/// consumes a single token, waiting for one to become available if the bucket is empty.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The configured number of requests per second.
+    rate: f64,
+    /// The maximum number of tokens in the bucket.
+    burst: f64,
+    /// The mutable state of the bucket.
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The number of tokens currently available.
+    tokens: f64,
+    /// The current number of requests per second, which is lowered when the server pushes back.
+    rate: f64,
+    /// The last time the bucket was refilled.
+    refilled_at: Instant,
+}
+
+impl State {
+    /// Adds the tokens accumulated since the last refill, up to `burst`.
+    fn refill(&mut self, burst: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(burst);
+        self.refilled_at = now;
+    }
+}
+
+impl RateLimiter {
+    /// Returns a new rate limiter allowing `requests_per_second` requests per second on average,
+    /// with bursts of up to `burst` requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::InvalidRateLimit`] if `requests_per_second` is not a positive, finite
+    /// number.
+    pub fn new(requests_per_second: f64, burst: u32) -> Result<RateLimiter, Error> {
+        if !(requests_per_second > 0.0 && requests_per_second.is_finite()) {
+            return Err(ErrorKind::InvalidRateLimit(requests_per_second).into());
+        }
+
+        let burst = f64::from(burst.max(1));
+
+        Ok(RateLimiter {
+            rate: requests_per_second,
+            burst,
+            state: Mutex::new(State {
+                tokens: burst,
+                rate: requests_per_second,
+                refilled_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// Waits until a token is available and consumes it.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                state.refill(self.burst);
+
+                if state.tokens >= 1.0 {
+                    state.tokens -= 1.0;
+
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - state.tokens) / state.rate)
+            };
+
+            trace!(?wait, "Waiting for rate limiter");
+            sleep(wait).await;
+        }
+    }
+
+    /// Lowers the current rate and drains the bucket, e.g. after a `429 Too Many Requests`.
+    pub fn slow_down(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.refill(self.burst);
+
+        state.rate = (state.rate / BACKOFF_FACTOR).max(self.rate * MIN_RATE_FRACTION);
+        state.tokens = state.tokens.min(0.0);
+
+        debug!(
+            requests_per_second = ?state.rate,
+            "Slowing down requests after pushback"
+        );
+    }
+
+    /// Gradually restores the configured rate after a successful request.
+    pub fn recover(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.rate < self.rate {
+            state.refill(self.burst);
+            state.rate = (state.rate + self.rate * RECOVERY_FRACTION).min(self.rate);
+
+            trace!(requests_per_second = ?state.rate, "Speeding up requests");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_rate(limiter: &RateLimiter) -> f64 {
+        limiter.state.lock().unwrap().rate
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = RateLimiter::new(rate, 1).unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                ErrorKind::InvalidRateLimit(rate).to_string()
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn allows_bursts_then_waits_for_tokens() {
+        let limiter = RateLimiter::new(2.0, 3).unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_up_to_burst() {
+        let limiter = RateLimiter::new(10.0, 2).unwrap();
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+        sleep(Duration::from_secs(10)).await;
+
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            limiter.acquire().await;
+        }
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slows_down_after_pushback() {
+        let limiter = RateLimiter::new(4.0, 4).unwrap();
+
+        limiter.slow_down();
+        assert_eq!(current_rate(&limiter), 2.0);
+
+        // The bucket is drained, so the next request waits for a token at the lowered rate
+        let start = Instant::now();
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+
+        for _ in 0..10 {
+            limiter.slow_down();
+        }
+
+        assert_eq!(current_rate(&limiter), 4.0 * MIN_RATE_FRACTION);
+    }
+
+    #[test]
+    fn recovers_gradually() {
+        let limiter = RateLimiter::new(10.0, 1).unwrap();
+
+        limiter.slow_down();
+        assert_eq!(current_rate(&limiter), 5.0);
+
+        limiter.recover();
+        assert_eq!(current_rate(&limiter), 6.0);
+
+        for _ in 0..10 {
+            limiter.recover();
+        }
+
+        assert_eq!(current_rate(&limiter), 10.0);
+    }
+}
@@ -0,0 +1,327 @@
+use std::path::Path;
+
+use clap::ArgEnum;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The character used in place of characters that aren't allowed in filenames.
+const REPLACEMENT: char = '_';
+
+/// Device names that Windows refuses as filenames, with or without an extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The file systems whose rules filenames must follow.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharacterSet {
+    /// Only `/` is replaced. Lengths are counted in bytes.
+    Posix,
+    /// `<>:"/\|?*` are replaced, trailing dots and spaces are removed and reserved device names
+    /// like `CON` are suffixed. Lengths are counted in UTF-16 code units.
+    Windows,
+    /// `<>:"/\|?*` are replaced and trailing dots and spaces are removed. Lengths are counted in
+    /// UTF-16 code units.
+    Exfat,
+}
+
+impl CharacterSet {
+    /// Returns whether `c` may not appear in a filename.
+    fn is_forbidden(self, c: char) -> bool {
+        // Control characters are technically allowed on POSIX, but only ever cause trouble
+        if c.is_control() {
+            return true;
+        }
+
+        match self {
+            CharacterSet::Posix => c == '/',
+            CharacterSet::Windows | CharacterSet::Exfat => {
+                matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+            }
+        }
+    }
+
+    /// Returns the length of `s` in the unit the file system limits filenames by.
+    fn len(self, s: &str) -> usize {
+        match self {
+            CharacterSet::Posix => s.len(),
+            CharacterSet::Windows | CharacterSet::Exfat => s.encode_utf16().count(),
+        }
+    }
+
+    /// Returns whether filenames may not end with a dot or a space.
+    fn trims_trailing_dots(self) -> bool {
+        self != CharacterSet::Posix
+    }
+
+    /// Returns `path` in a form that is equal for paths that the file system considers the same
+    /// file, since Windows and exFAT file systems ignore case.
+    pub fn path_key(self, path: &Path) -> String {
+        let path = path.to_string_lossy();
+
+        match self {
+            CharacterSet::Posix => path.into_owned(),
+            CharacterSet::Windows | CharacterSet::Exfat => path.to_lowercase(),
+        }
+    }
+}
+
+/// The Unicode normalization form applied to filenames.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Leaves filenames as they are.
+    None,
+    /// Canonical composition, as used by most file systems.
+    Nfc,
+    /// Canonical decomposition, as used by HFS+ on macOS.
+    Nfd,
+}
+
+/// Turns arbitrary text into names that are valid files or directories.
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    pub character_set: CharacterSet,
+    /// The maximum length of a name, in the unit of the [`CharacterSet`].
+    pub max_length: usize,
+    pub normalization: Normalization,
+}
+
+impl Sanitizer {
+    /// Returns `name` with `suffix` appended as a valid file or directory name.
+    ///
+    /// `name` is truncated so that the suffix, e.g. a file extension, is always kept, unless the
+    /// suffix doesn't leave room for a single character of the name. The suffix itself is
+    /// expected to be valid already.
+    pub fn sanitize(&self, name: &str, suffix: &str) -> String {
+        let charset = self.character_set;
+        let max_length = self.max_length.max(1);
+
+        let mut suffix = suffix;
+
+        while charset.len(suffix) >= max_length {
+            let mut chars = suffix.chars();
+            chars.next();
+            suffix = chars.as_str();
+        }
+
+        let max_name_length = max_length - charset.len(suffix);
+        let name: String = match self.normalization {
+            Normalization::None => name.to_string(),
+            Normalization::Nfc => name.nfc().collect(),
+            Normalization::Nfd => name.nfd().collect(),
+        };
+
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if charset.is_forbidden(c) {
+                    REPLACEMENT
+                } else {
+                    c
+                }
+            })
+            .collect();
+
+        // Leading dots would hide the file on POSIX systems and `.` and `..` aren't names at all
+        let name = name.trim().trim_start_matches('.');
+
+        // Whole grapheme clusters are kept, so that no character loses its combining marks
+        let mut truncated = String::new();
+
+        for grapheme in name.graphemes(true) {
+            if charset.len(&truncated) + charset.len(grapheme) > max_name_length {
+                break;
+            }
+
+            truncated.push_str(grapheme);
+        }
+
+        let mut name = truncated.trim_end().to_string();
+
+        if charset.trims_trailing_dots() {
+            name = name.trim_end_matches(['.', ' ']).to_string();
+        }
+
+        if name.is_empty() {
+            name.push(REPLACEMENT);
+        }
+
+        if charset == CharacterSet::Windows {
+            let stem = name.split('.').next().unwrap_or_default().trim_end();
+
+            // Reserved names are ASCII, so the stem can be cut at any byte
+            if WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+            {
+                let end = stem.len();
+
+                if charset.len(&name) < max_name_length {
+                    name.insert(end, REPLACEMENT);
+                } else {
+                    name.replace_range(end - 1..end, &REPLACEMENT.to_string());
+                }
+            }
+        }
+
+        name + suffix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitizer(character_set: CharacterSet, max_length: usize) -> Sanitizer {
+        Sanitizer {
+            character_set,
+            max_length,
+            normalization: Normalization::Nfc,
+        }
+    }
+
+    #[test]
+    fn replaces_forbidden_characters() {
+        let windows = sanitizer(CharacterSet::Windows, 255);
+        let posix = sanitizer(CharacterSet::Posix, 255);
+
+        assert_eq!(windows.sanitize("AC/DC: Live?", ".mp4"), "AC_DC_ Live_.mp4");
+        assert_eq!(posix.sanitize("AC/DC: Live?", ".mp4"), "AC_DC: Live?.mp4");
+        assert_eq!(posix.sanitize("Tab\there", ""), "Tab_here");
+    }
+
+    #[test]
+    fn suffixes_reserved_names() {
+        let windows = sanitizer(CharacterSet::Windows, 255);
+
+        assert_eq!(windows.sanitize("CON", ".mp4"), "CON_.mp4");
+        assert_eq!(windows.sanitize("lpt1.part", ""), "lpt1_.part");
+        assert_eq!(windows.sanitize("Nul ", ""), "Nul_");
+        assert_eq!(windows.sanitize("CONSOLE", ""), "CONSOLE");
+        assert_eq!(windows.sanitize("COM10", ""), "COM10");
+
+        // Without room for the suffix, the reserved name is changed instead
+        assert_eq!(
+            sanitizer(CharacterSet::Windows, 7).sanitize("AUX", ".mp4"),
+            "AU_.mp4"
+        );
+
+        for charset in [CharacterSet::Posix, CharacterSet::Exfat] {
+            assert_eq!(sanitizer(charset, 255).sanitize("CON", ".mp4"), "CON.mp4");
+        }
+    }
+
+    #[test]
+    fn removes_leading_and_trailing_dots() {
+        let windows = sanitizer(CharacterSet::Windows, 255);
+        let posix = sanitizer(CharacterSet::Posix, 255);
+
+        assert_eq!(windows.sanitize("..hidden", ""), "hidden");
+        assert_eq!(windows.sanitize("Film ... ", ""), "Film");
+        assert_eq!(posix.sanitize("Film...", ""), "Film...");
+        assert_eq!(posix.sanitize("..", ""), "_");
+    }
+
+    #[test]
+    fn normalizes_names() {
+        let decomposed = "Rene\u{301}";
+        let composed = "Ren\u{e9}";
+        let mut nfd = sanitizer(CharacterSet::Posix, 255);
+        nfd.normalization = Normalization::Nfd;
+        let mut none = sanitizer(CharacterSet::Posix, 255);
+        none.normalization = Normalization::None;
+
+        assert_eq!(
+            sanitizer(CharacterSet::Posix, 255).sanitize(decomposed, ""),
+            composed
+        );
+        assert_eq!(nfd.sanitize(composed, ""), decomposed);
+        assert_eq!(none.sanitize(decomposed, ""), decomposed);
+    }
+
+    #[test]
+    fn truncates_to_the_maximum_length() {
+        // Lengths are in bytes on POSIX and UTF-16 code units elsewhere
+        assert_eq!(
+            sanitizer(CharacterSet::Posix, 9).sanitize("Blåbær", ".mp4"),
+            "Blåb.mp4"
+        );
+        assert_eq!(
+            sanitizer(CharacterSet::Exfat, 9).sanitize("Blåbær", ".mp4"),
+            "Blåbæ.mp4"
+        );
+        assert_eq!(
+            sanitizer(CharacterSet::Exfat, 5).sanitize("\u{1f3ac}\u{1f3ac}", ".mp4"),
+            "_.mp4"
+        );
+
+        // Spaces left at the end by truncation are removed
+        assert_eq!(
+            sanitizer(CharacterSet::Posix, 7).sanitize("Ab Cd", ".mp4"),
+            "Ab.mp4"
+        );
+    }
+
+    #[test]
+    fn truncates_whole_graphemes() {
+        let mut nfd = sanitizer(CharacterSet::Posix, 7);
+        nfd.normalization = Normalization::Nfd;
+
+        // `e` and the combining acute accent take up 3 bytes, which don't fit after `Ren`
+        assert_eq!(nfd.sanitize("Ren\u{e9}e", ".mp4"), "Ren.mp4");
+        assert_eq!(nfd.sanitize("Ren\u{e9}e", ""), "Rene\u{301}e");
+    }
+
+    #[test]
+    fn never_exceeds_the_maximum_length() {
+        let suffix = " [123]-featurette.mp4";
+
+        for max_length in 0..=30 {
+            for charset in [
+                CharacterSet::Posix,
+                CharacterSet::Windows,
+                CharacterSet::Exfat,
+            ] {
+                let name = sanitizer(charset, max_length).sanitize("A Film", suffix);
+
+                assert!(
+                    charset.len(&name) <= max_length.max(1),
+                    "`{}` is longer than {}",
+                    name,
+                    max_length
+                );
+                assert!(!name.is_empty());
+            }
+        }
+
+        assert_eq!(
+            sanitizer(CharacterSet::Posix, 4).sanitize("Film", ".mp4"),
+            "Fmp4"
+        );
+    }
+
+    #[test]
+    fn falls_back_for_empty_names() {
+        let windows = sanitizer(CharacterSet::Windows, 255);
+
+        assert_eq!(windows.sanitize("", ".mp4"), "_.mp4");
+        assert_eq!(windows.sanitize("   ", ""), "_");
+        assert_eq!(windows.sanitize(". .", ""), "_");
+        assert_eq!(windows.sanitize("?", ""), "_");
+    }
+
+    #[test]
+    fn compares_paths_by_file_system() {
+        let path = Path::new("films/Dir - Film.mp4");
+
+        assert_eq!(
+            CharacterSet::Windows.path_key(path),
+            CharacterSet::Exfat.path_key(Path::new("FILMS/dir - film.MP4"))
+        );
+        assert_ne!(
+            CharacterSet::Posix.path_key(path),
+            CharacterSet::Posix.path_key(Path::new("FILMS/dir - film.MP4"))
+        );
+    }
+}
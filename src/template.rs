@@ -0,0 +1,441 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::database::{DownloadKind, Film};
+use crate::error::{Error, ErrorKind};
+use crate::sanitize::Sanitizer;
+
+/// The extension of downloaded films.
+const EXTENSION: &str = "mp4";
+/// The title used for films with neither a title nor an original title.
+const UNKNOWN_TITLE: &str = "Untitled";
+/// The director used for films without a director.
+const UNKNOWN_DIRECTOR: &str = "Unknown Director";
+/// The year used for films with neither a production year nor a festival year.
+const UNKNOWN_YEAR: &str = "Unknown Year";
+
+/// A value of a film that can be used in a filename template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateField {
+    Id,
+    Title,
+    OriginalTitle,
+    Director,
+    ProductionYear,
+    /// The title of the festival year the film was shown at.
+    FestivalYear,
+    /// The title of the first genre of the film.
+    Genre,
+    /// The code of the first country of the film.
+    Country,
+}
+
+/// The placeholder names of all [`TemplateField`]s.
+const FIELDS: &[(&str, TemplateField)] = &[
+    ("id", TemplateField::Id),
+    ("title", TemplateField::Title),
+    ("original_title", TemplateField::OriginalTitle),
+    ("director", TemplateField::Director),
+    ("production_year", TemplateField::ProductionYear),
+    ("festival_year", TemplateField::FestivalYear),
+    ("genre", TemplateField::Genre),
+    ("country", TemplateField::Country),
+];
+
+impl TemplateField {
+    /// Returns the value of the field for `film`.
+    ///
+    /// Films without a title are named by their original title, films without a production year
+    /// by their festival year, and films without a director as [`UNKNOWN_DIRECTOR`]. Other
+    /// missing values are empty.
+    fn value(self, film: &Film) -> String {
+        fn opt<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map_or_else(String::new, T::to_string)
+        }
+
+        let festival_year = film.year.as_ref().and_then(|year| year.title.clone());
+
+        match self {
+            TemplateField::Id => film.id.to_string(),
+            TemplateField::Title => film
+                .title
+                .clone()
+                .or_else(|| film.original_title.clone())
+                .unwrap_or_else(|| UNKNOWN_TITLE.to_string()),
+            TemplateField::OriginalTitle => opt(&film.original_title),
+            TemplateField::Director => film
+                .director
+                .clone()
+                .unwrap_or_else(|| UNKNOWN_DIRECTOR.to_string()),
+            TemplateField::ProductionYear => film
+                .production_year
+                .map(|year| year.to_string())
+                .or(festival_year)
+                .unwrap_or_else(|| UNKNOWN_YEAR.to_string()),
+            TemplateField::FestivalYear => festival_year.unwrap_or_default(),
+            TemplateField::Genre => film
+                .genres
+                .first()
+                .map(|genre| genre.title.clone())
+                .unwrap_or_default(),
+            TemplateField::Country => film
+                .countries
+                .first()
+                .map(|country| country.code.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(TemplateField),
+}
+
+/// A template for the path of a downloaded film relative to the output directory, without the
+/// file extension, e.g. `{production_year}/{director} - {title}`.
+///
+/// Placeholders are the names of [`TemplateField`]s in braces, and `{{` and `}}` are literal
+/// braces. Each `/`-separated component is sanitized separately, so values containing `/` never
+/// create directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilenameTemplate {
+    components: Vec<Vec<Segment>>,
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = Error;
+
+    /// Parses a filename template.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::InvalidFilenameTemplate`] if the template has an unknown placeholder,
+    /// unbalanced braces, or an empty, `.` or `..` component.
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| {
+            Error::from(ErrorKind::InvalidFilenameTemplate(
+                template.to_string(),
+                reason,
+            ))
+        };
+
+        let mut components = vec![];
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(invalid("unclosed placeholder".to_string())),
+                        }
+                    }
+
+                    let field = FIELDS
+                        .iter()
+                        .find(|(field_name, _)| *field_name == name)
+                        .map(|(_, field)| *field)
+                        .ok_or_else(|| invalid(format!("unknown placeholder `{{{}}}`", name)))?;
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    segments.push(Segment::Field(field));
+                }
+                '}' => return Err(invalid("unmatched `}`".to_string())),
+                '/' => {
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    components.push(std::mem::take(&mut segments));
+                }
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        components.push(segments);
+
+        for component in &components {
+            match component.as_slice() {
+                [] => return Err(invalid("empty path component".to_string())),
+                [Segment::Literal(literal)] if literal.trim() == "." || literal.trim() == ".." => {
+                    return Err(invalid(format!("`{}` is not allowed", literal.trim())))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(FilenameTemplate { components })
+    }
+}
+
+impl FilenameTemplate {
+    /// Returns the path of `film` as described by the template, with `suffix` appended to the
+    /// filename.
+    pub fn render(&self, film: &Film, sanitizer: &Sanitizer, suffix: &str) -> PathBuf {
+        let last = self.components.len() - 1;
+
+        self.components
+            .iter()
+            .enumerate()
+            .map(|(index, segments)| {
+                let name: String = segments
+                    .iter()
+                    .map(|segment| match segment {
+                        Segment::Literal(literal) => literal.clone(),
+                        Segment::Field(field) => field.value(film),
+                    })
+                    .collect();
+
+                sanitizer.sanitize(&name, if index == last { suffix } else { "" })
+            })
+            .collect()
+    }
+}
+
+/// Where and how downloaded films are stored.
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    pub output_dir: PathBuf,
+    pub template: FilenameTemplate,
+    pub sanitizer: Sanitizer,
+}
+
+impl OutputLayout {
+    /// Returns the path to download the given `kind` of `film` to.
+    ///
+    /// If `disambiguate` is set, the id of the film is added to the filename to tell it apart
+    /// from other films with the same details.
+    pub fn film_path(&self, film: &Film, kind: DownloadKind, disambiguate: bool) -> PathBuf {
+        let mut suffix = String::new();
+
+        if disambiguate {
+            suffix.push_str(&format!(" [{}]", film.id));
+        }
+
+        // Greetings are named as featurettes so that media servers list them as extras of the film
+        if kind == DownloadKind::Greeting {
+            suffix.push_str("-featurette");
+        }
+
+        suffix.push('.');
+        suffix.push_str(EXTENSION);
+
+        self.output_dir
+            .join(self.template.render(film, &self.sanitizer, &suffix))
+    }
+
+    /// Returns the ids of the `films` that must be downloaded with their id in the filename.
+    ///
+    /// These are all films whose details render to the same path as another film, regardless of
+    /// which of them is downloaded first, and films whose path another film has already been
+    /// downloaded to. Paths are compared the way the file system of the
+    /// [`CharacterSet`](crate::sanitize::CharacterSet) would.
+    pub fn films_to_disambiguate(&self, films: &[Film]) -> HashSet<u64> {
+        let charset = self.sanitizer.character_set;
+        let paths: Vec<(u64, String)> = films
+            .iter()
+            .map(|film| {
+                let path = self.film_path(film, DownloadKind::Film, false);
+
+                (film.id, charset.path_key(&path))
+            })
+            .collect();
+
+        let mut owners: HashMap<&str, HashSet<u64>> = HashMap::new();
+
+        for (film_id, path) in &paths {
+            owners.entry(path).or_default().insert(*film_id);
+        }
+
+        let downloads: Vec<(u64, String)> = films
+            .iter()
+            .filter_map(|film| {
+                let download = film.download.as_ref()?;
+
+                Some((film.id, charset.path_key(download.path.as_ref())))
+            })
+            .collect();
+
+        for (film_id, path) in &downloads {
+            if let Some(owners) = owners.get_mut(path.as_str()) {
+                owners.insert(*film_id);
+            }
+        }
+
+        paths
+            .iter()
+            .filter(|(_, path)| owners[path.as_str()].len() > 1)
+            .map(|(film_id, _)| *film_id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use std::path::Path;
+
+    use super::*;
+    use crate::export::ExportRecord;
+    use crate::sanitize::{CharacterSet, Normalization};
+
+    fn film(record: Value) -> Film {
+        Film::from(serde_json::from_value::<ExportRecord>(record).unwrap())
+    }
+
+    fn layout(template: &str, character_set: CharacterSet) -> OutputLayout {
+        OutputLayout {
+            output_dir: PathBuf::from("films"),
+            template: template.parse().unwrap(),
+            sanitizer: Sanitizer {
+                character_set,
+                max_length: 255,
+                normalization: Normalization::Nfc,
+            },
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let templates = [
+            ("{name}", "unknown placeholder `{name}`"),
+            ("{title", "unclosed placeholder"),
+            ("title}", "unmatched `}`"),
+            ("{director}//{title}", "empty path component"),
+            ("{director}/", "empty path component"),
+            ("../{title}", "`..` is not allowed"),
+            ("{title}/ . ", "`.` is not allowed"),
+        ];
+
+        for (template, reason) in templates {
+            let err = template.parse::<FilenameTemplate>().unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                ErrorKind::InvalidFilenameTemplate(template.to_string(), reason.to_string())
+                    .to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn renders_paths() {
+        let layout = layout(
+            "{production_year}/{director} - {title} {{{id}}}",
+            CharacterSet::Windows,
+        );
+        let film = film(json!({
+            "id": 7,
+            "title": "AC/DC: Live",
+            "director": "Someone",
+            "production_year": 2020,
+        }));
+
+        assert_eq!(
+            layout.film_path(&film, DownloadKind::Film, false),
+            Path::new("films/2020/Someone - AC_DC_ Live {7}.mp4")
+        );
+        assert_eq!(
+            layout.film_path(&film, DownloadKind::Greeting, true),
+            Path::new("films/2020/Someone - AC_DC_ Live {7} [7]-featurette.mp4")
+        );
+    }
+
+    #[test]
+    fn renders_fallbacks_for_missing_details() {
+        let layout = layout(
+            "{production_year}/{director} - {title}{genre}",
+            CharacterSet::Windows,
+        );
+
+        assert_eq!(
+            layout.film_path(&film(json!({ "id": 1 })), DownloadKind::Film, false),
+            Path::new("films/Unknown Year/Unknown Director - Untitled.mp4")
+        );
+        assert_eq!(
+            layout.film_path(
+                &film(json!({
+                    "id": 2,
+                    "original_title": "Originaltitel",
+                    "festival_year_id": 1,
+                    "festival_year": "2021",
+                })),
+                DownloadKind::Film,
+                false
+            ),
+            Path::new("films/2021/Unknown Director - Originaltitel.mp4")
+        );
+    }
+
+    #[test]
+    fn disambiguates_films_sharing_a_path() {
+        let films = || {
+            vec![
+                film(json!({ "id": 1, "title": "Alpha", "director": "Someone" })),
+                film(json!({ "id": 2, "title": "alpha", "director": "someone" })),
+                film(json!({ "id": 3, "title": "Beta", "director": "Someone" })),
+                film(json!({ "id": 4, "title": "Gamma", "director": "Someone" })),
+                film(json!({ "id": 5, "title": "Delta", "director": "Someone" })),
+                film(json!({ "id": 6, "title": "Delta", "director": "Someone" })),
+            ]
+        };
+
+        // All films sharing a path are disambiguated, regardless of their order
+        let windows = layout("{director} - {title}", CharacterSet::Windows);
+        let mut reversed = films();
+        reversed.reverse();
+
+        assert_eq!(
+            windows.films_to_disambiguate(&films()),
+            HashSet::from([1, 2, 5, 6])
+        );
+        assert_eq!(
+            windows.films_to_disambiguate(&reversed),
+            HashSet::from([1, 2, 5, 6])
+        );
+
+        let posix = layout("{director} - {title}", CharacterSet::Posix);
+        assert_eq!(posix.films_to_disambiguate(&films()), HashSet::from([5, 6]));
+    }
+
+    #[test]
+    fn disambiguates_films_whose_path_is_downloaded_to() {
+        let layout = layout("{title}", CharacterSet::Exfat);
+        let films = [
+            film(json!({ "id": 1, "title": "Alpha" })),
+            film(json!({
+                "id": 2,
+                "title": "Beta",
+                "download_path": "films/ALPHA.mp4",
+                "download_finished_at": "2021-01-01T00:00:00Z",
+            })),
+        ];
+
+        assert_eq!(layout.films_to_disambiguate(&films), HashSet::from([1]));
+    }
+}
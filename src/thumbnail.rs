@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::{debug, error, instrument, trace};
+
+use crate::database::{Database, FilmThumbnail};
+use crate::error::Error;
+
+/// The image extensions kept from thumbnail urls. Anything else is assumed to be a JPEG.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif"];
+
+/// A thumbnail that has been downloaded next to a film.
+#[derive(Debug)]
+pub struct ThumbnailFile {
+    pub resolution: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// The hex-encoded SHA-256 hash of the image.
+    pub sha256: String,
+}
+
+/// Returns the number of pixels described by a thumbnail resolution like `640x360` or `640`, so
+/// that thumbnails can be ranked by size.
+fn resolution_area(resolution: &str) -> Option<u64> {
+    let mut dimensions = resolution
+        .split(['x', 'X', '*'])
+        .map(|dimension| dimension.trim().parse::<u64>());
+
+    match (dimensions.next(), dimensions.next()) {
+        (Some(Ok(width)), Some(Ok(height))) => width.checked_mul(height),
+        (Some(Ok(width)), None) => width.checked_mul(width),
+        _ => None,
+    }
+}
+
+/// Returns the extension of the image at `url`, defaulting to `jpg`.
+fn image_extension(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+
+    path.rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase())
+        .filter(|extension| IMAGE_EXTENSIONS.contains(&extension.as_str()))
+        .unwrap_or_else(|| "jpg".to_string())
+}
+
+/// Returns the name of the file a thumbnail with the given `resolution` is stored in, without an
+/// extension.
+///
+/// Resolutions come from the API and are only used as-is if they look like `640x360` or `640`, so
+/// that they can't escape the thumbnail directory or be invalid filenames. Anything else is named
+/// after a hash of the resolution, which keeps distinct resolutions apart.
+fn thumbnail_name(resolution: &str) -> String {
+    let is_plain =
+        !resolution.is_empty() && resolution.chars().all(|c| c.is_ascii_digit() || c == 'x');
+
+    if is_plain {
+        resolution.to_string()
+    } else {
+        let hash = format!("{:x}", Sha256::digest(resolution.as_bytes()));
+
+        format!("thumbnail-{}", &hash[..16])
+    }
+}
+
+/// Returns the directory the thumbnails of the film at `video_path` are stored in.
+pub fn thumbnail_dir(video_path: &Path) -> PathBuf {
+    video_path.with_extension("thumbnails")
+}
+
+/// Returns the path of the poster of the film at `video_path`.
+///
+/// Films share a directory, so the poster is named `<film>-poster.<ext>` rather than
+/// `poster.<ext>`, which Kodi and Jellyfin both recognize.
+pub fn poster_path(video_path: &Path, extension: &str) -> PathBuf {
+    let stem = video_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    video_path.with_file_name(format!("{}-poster.{}", stem, extension))
+}
+
+/// Downloads the thumbnail at `url` to `path`.
+async fn download_thumbnail(
+    http: &reqwest::Client,
+    resolution: &str,
+    url: &str,
+    path: PathBuf,
+) -> Result<ThumbnailFile, Error> {
+    trace!(resolution, url, ?path, "Downloading thumbnail");
+
+    let bytes = http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let sha256 = format!("{:x}", Sha256::digest(&bytes));
+
+    fs::write(&path, &bytes).await?;
+
+    Ok(ThumbnailFile {
+        resolution: resolution.to_string(),
+        path,
+        size: bytes.len() as u64,
+        sha256,
+    })
+}
+
+/// Returns the file a thumbnail has already been downloaded to, if it has been.
+fn stored_file(thumbnail: &FilmThumbnail) -> Option<ThumbnailFile> {
+    Some(ThumbnailFile {
+        resolution: thumbnail.resolution.clone(),
+        path: PathBuf::from(thumbnail.path.as_ref()?),
+        size: thumbnail.size?,
+        sha256: thumbnail.sha256.clone()?,
+    })
+}
+
+/// Downloads the thumbnails of the film with the given `film_id` that haven't been downloaded yet
+/// next to the film at `video_path`, records them in the database and copies the largest one to
+/// the poster path.
+///
+/// Thumbnails that can't be downloaded are logged and skipped, so that one broken url doesn't
+/// keep the rest of them or the poster from being saved.
+///
+/// Returns the path of the poster, if any of the thumbnails of the film have been downloaded.
+#[instrument(err, skip(http, db))]
+pub async fn download_thumbnails(
+    http: &reqwest::Client,
+    db: &Database,
+    film_id: u64,
+    video_path: &Path,
+) -> Result<Option<PathBuf>, Error> {
+    let thumbnails = db.get_film_thumbnail_files(film_id)?;
+
+    if thumbnails.is_empty() {
+        return Ok(None);
+    }
+
+    let dir = thumbnail_dir(video_path);
+    fs::create_dir_all(&dir).await?;
+
+    let mut files = Vec::with_capacity(thumbnails.len());
+
+    for thumbnail in thumbnails {
+        if let Some(file) = stored_file(&thumbnail) {
+            files.push(file);
+            continue;
+        }
+
+        let url = match thumbnail.url {
+            Some(url) => url,
+            None => continue,
+        };
+        let resolution = thumbnail.resolution;
+        let path = dir.join(format!(
+            "{}.{}",
+            thumbnail_name(&resolution),
+            image_extension(&url)
+        ));
+
+        let file = match download_thumbnail(http, &resolution, &url, path).await {
+            Ok(file) => file,
+            Err(err) => {
+                error!(
+                    resolution = resolution.as_str(),
+                    "Could not download thumbnail: {:?}", err
+                );
+                continue;
+            }
+        };
+
+        if let Err(err) = db.update_film_thumbnail_file(
+            film_id,
+            &file.resolution,
+            &file.path.to_string_lossy(),
+            file.size,
+            &file.sha256,
+        ) {
+            error!(
+                resolution = resolution.as_str(),
+                "Could not record thumbnail file: {:?}", err
+            );
+        }
+
+        files.push(file);
+    }
+
+    // Rank by the resolution when it can be parsed, and by file size otherwise
+    let best = match files
+        .iter()
+        .max_by_key(|file| (resolution_area(&file.resolution), file.size))
+    {
+        Some(best) => best,
+        None => return Ok(None),
+    };
+
+    let extension = best
+        .path
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "jpg".to_string());
+    let poster = poster_path(video_path, &extension);
+
+    debug!(
+        resolution = best.resolution.as_str(),
+        ?poster,
+        "Saving poster"
+    );
+
+    fs::copy(&best.path, &poster).await?;
+
+    Ok(Some(poster))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_plain_resolutions_as_names() {
+        assert_eq!(thumbnail_name("640x360"), "640x360");
+        assert_eq!(thumbnail_name("1280"), "1280");
+    }
+
+    #[test]
+    fn hashes_other_resolutions() {
+        for resolution in ["../../poster", "640/360", "C:\\x", "", "640X360"] {
+            let name = thumbnail_name(resolution);
+
+            assert!(name.starts_with("thumbnail-"), "{}", name);
+            assert!(
+                name[10..].chars().all(|c| c.is_ascii_hexdigit()),
+                "{}",
+                name
+            );
+        }
+
+        assert_ne!(thumbnail_name("640/360"), thumbnail_name("640\\360"));
+    }
+}
@@ -0,0 +1,1272 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use futures::future::BoxFuture;
+use futures::{stream, FutureExt, StreamExt, TryStreamExt};
+use indicatif::HumanBytes;
+use reqwest::{header, Url};
+use serde::Deserialize;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, instrument, trace, warn};
+
+use crate::downloader::{Downloader, REFERER};
+use crate::error::{Error, ErrorKind};
+use crate::mp4::{self, FragmentWriter};
+use crate::progress::{DownloadProgress, Progress};
+
+/// The number of segments that are downloaded at a time.
+const SEGMENT_CONCURRENCY: usize = 4;
+
+#[derive(Deserialize, Debug)]
+struct PlayerConfig {
+    request: PlayerRequest,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlayerRequest {
+    files: PlayerFiles,
+}
+
+/// The renditions of a video, as listed in its player config.
+#[derive(Deserialize, Debug)]
+struct PlayerFiles {
+    /// Single files with both video and audio.
+    #[serde(default)]
+    progressive: Vec<ProgressiveFile>,
+    /// Vimeo's JSON flavour of DASH.
+    dash: Option<StreamingFiles>,
+    hls: Option<StreamingFiles>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProgressiveFile {
+    url: String,
+    #[serde(default)]
+    width: u64,
+    #[serde(default)]
+    height: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamingFiles {
+    default_cdn: Option<String>,
+    #[serde(default)]
+    cdns: HashMap<String, Cdn>,
+}
+
+impl StreamingFiles {
+    /// Returns the manifest url of the default CDN, or any CDN if there is no default.
+    fn url(&self) -> Option<&str> {
+        self.default_cdn
+            .as_ref()
+            .and_then(|cdn| self.cdns.get(cdn))
+            .or_else(|| self.cdns.values().next())
+            .map(|cdn| cdn.url.as_str())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Cdn {
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DashManifest {
+    #[serde(default)]
+    base_url: String,
+    #[serde(default)]
+    video: Vec<DashStream>,
+    #[serde(default)]
+    audio: Vec<DashStream>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DashStream {
+    #[serde(default)]
+    base_url: String,
+    #[serde(default)]
+    bitrate: u64,
+    #[serde(default)]
+    height: u64,
+    /// The base64 encoded init segment.
+    init_segment: Option<String>,
+    segments: Vec<DashSegment>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DashSegment {
+    url: String,
+}
+
+/// The init segment of a stream.
+#[derive(Debug)]
+enum InitSegment {
+    Data(Vec<u8>),
+    Url(Url),
+}
+
+/// A stream split into segments.
+#[derive(Debug)]
+struct Track {
+    init: Option<InitSegment>,
+    segments: Vec<Url>,
+}
+
+/// A variant stream in an HLS master playlist.
+#[derive(Debug)]
+struct HlsVariant {
+    bandwidth: u64,
+    height: u64,
+    audio_group: Option<String>,
+    url: Url,
+}
+
+/// Where the media of a rendition is downloaded from.
+#[derive(Debug)]
+enum RenditionSource {
+    /// A single file with both video and audio.
+    File(String),
+    /// A video stream and an optional separate audio stream.
+    Tracks { video: Track, audio: Option<Track> },
+}
+
+/// A rendition of a video that can be downloaded.
+#[derive(Debug)]
+struct Rendition {
+    /// How the rendition is served, e.g. `DASH`.
+    protocol: &'static str,
+    height: u64,
+    /// The bitrate of the video in bits per second, or 0 if it isn't known.
+    bitrate: u64,
+    source: RenditionSource,
+}
+
+fn vimeo_error<T: ToString>(reason: T) -> Error {
+    ErrorKind::VimeoError(reason.to_string()).into()
+}
+
+fn join_url(base: &Url, url: &str) -> Result<Url, Error> {
+    base.join(url)
+        .map_err(|err| vimeo_error(format!("invalid url `{}`: {}", url, err)))
+}
+
+/// Returns the attributes of an HLS tag like `#EXT-X-STREAM-INF:BANDWIDTH=1,CODECS="a,b"`.
+fn hls_attributes(line: &str) -> HashMap<String, String> {
+    let attributes = line
+        .split_once(':')
+        .map_or("", |(_, attributes)| attributes);
+    let mut res = HashMap::new();
+    let mut pair = String::new();
+    let mut quoted = false;
+
+    for c in attributes.chars().chain(std::iter::once(',')) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some((key, value)) = pair.split_once('=') {
+                    res.insert(key.trim().to_string(), value.to_string());
+                }
+
+                pair.clear();
+            }
+            _ => pair.push(c),
+        }
+    }
+
+    res
+}
+
+/// Returns the best rendition in the DASH manifest `text` served at `url`, which combines the
+/// best video stream with the best audio stream.
+fn dash_rendition(url: &Url, text: &str) -> Result<Rendition, Error> {
+    let jd = &mut serde_json::Deserializer::from_str(text);
+    let manifest: DashManifest = serde_path_to_error::deserialize(jd)
+        .map_err(|err| Error::from(ErrorKind::JsonDeserializationFailed(err)))?;
+    let base_url = join_url(url, &manifest.base_url)?;
+
+    let track = |stream: &DashStream| -> Result<Track, Error> {
+        let stream_url = join_url(&base_url, &stream.base_url)?;
+        let init = match &stream.init_segment {
+            Some(init) => Some(InitSegment::Data(
+                base64::decode(init).map_err(vimeo_error)?,
+            )),
+            None => None,
+        };
+        let segments = stream
+            .segments
+            .iter()
+            .map(|segment| join_url(&stream_url, &segment.url))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Track { init, segments })
+    };
+
+    let video = manifest
+        .video
+        .iter()
+        .max_by_key(|stream| (stream.height, stream.bitrate))
+        .ok_or_else(|| vimeo_error("the DASH manifest has no video streams"))?;
+    let audio = manifest.audio.iter().max_by_key(|stream| stream.bitrate);
+
+    debug!(
+        height = video.height,
+        video_bitrate = video.bitrate,
+        audio_bitrate = ?audio.map(|audio| audio.bitrate),
+        "Selected DASH streams"
+    );
+
+    Ok(Rendition {
+        protocol: "DASH",
+        height: video.height,
+        bitrate: video.bitrate,
+        source: RenditionSource::Tracks {
+            video: track(video)?,
+            audio: audio.map(track).transpose()?,
+        },
+    })
+}
+
+/// Returns the variants of the HLS master playlist `text` served at `url`, and the urls of the
+/// media playlists of its audio groups.
+fn parse_hls_master(
+    url: &Url,
+    text: &str,
+) -> Result<(Vec<HlsVariant>, HashMap<String, Url>), Error> {
+    let mut variants = vec![];
+    let mut audio_groups = HashMap::new();
+    let mut lines = text.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        if line.starts_with("#EXT-X-STREAM-INF:") {
+            let attributes = hls_attributes(line);
+
+            if let Some(uri) = lines.find(|line| !line.is_empty() && !line.starts_with('#')) {
+                variants.push(HlsVariant {
+                    bandwidth: attributes
+                        .get("BANDWIDTH")
+                        .and_then(|bandwidth| bandwidth.parse().ok())
+                        .unwrap_or_default(),
+                    height: attributes
+                        .get("RESOLUTION")
+                        .and_then(|resolution| resolution.split_once('x'))
+                        .and_then(|(_, height)| height.parse().ok())
+                        .unwrap_or_default(),
+                    audio_group: attributes.get("AUDIO").cloned(),
+                    url: join_url(url, uri)?,
+                });
+            }
+        } else if line.starts_with("#EXT-X-MEDIA:") {
+            let attributes = hls_attributes(line);
+
+            if let (Some("AUDIO"), Some(group), Some(uri)) = (
+                attributes.get("TYPE").map(String::as_str),
+                attributes.get("GROUP-ID"),
+                attributes.get("URI"),
+            ) {
+                audio_groups
+                    .entry(group.clone())
+                    .or_insert(join_url(url, uri)?);
+            }
+        }
+    }
+
+    Ok((variants, audio_groups))
+}
+
+/// Returns the best of the HLS `variants` along with the url of its audio media playlist, if it
+/// has separate audio, or `None` if there are no variants.
+///
+/// Variants whose audio group is missing from `audio_groups` would be downloaded without sound,
+/// so they're skipped.
+///
+/// # Errors
+///
+/// Returns [`ErrorKind::VimeoError`] if the audio group of every variant is missing.
+fn select_hls_variant(
+    variants: Vec<HlsVariant>,
+    audio_groups: &HashMap<String, Url>,
+) -> Result<Option<(HlsVariant, Option<Url>)>, Error> {
+    if variants.is_empty() {
+        return Ok(None);
+    }
+
+    let (usable, missing_audio): (Vec<_>, Vec<_>) = variants.into_iter().partition(|variant| {
+        variant
+            .audio_group
+            .as_ref()
+            .is_none_or(|group| audio_groups.contains_key(group))
+    });
+
+    if !missing_audio.is_empty() {
+        warn!(
+            num_variants = missing_audio.len(),
+            "Skipping HLS variants whose audio group is missing from the master playlist"
+        );
+    }
+
+    let variant = usable
+        .into_iter()
+        .max_by_key(|variant| (variant.height, variant.bandwidth))
+        .ok_or_else(|| vimeo_error("the audio stream can't be combined with the video stream"))?;
+    let audio_url = variant
+        .audio_group
+        .as_ref()
+        .and_then(|group| audio_groups.get(group))
+        .cloned();
+
+    Ok(Some((variant, audio_url)))
+}
+
+/// Returns the segments of the HLS media playlist `text` served at `url`.
+fn parse_hls_media(url: &Url, text: &str) -> Result<Track, Error> {
+    let mut init = None;
+    let mut segments = vec![];
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with("#EXT-X-MAP:") {
+            if let Some(uri) = hls_attributes(line).get("URI") {
+                init = Some(InitSegment::Url(join_url(url, uri)?));
+            }
+        } else if !line.is_empty() && !line.starts_with('#') {
+            segments.push(join_url(url, line)?);
+        }
+    }
+
+    Ok(Track { init, segments })
+}
+
+/// Returns the path a download is written to until it's finished.
+fn part_path(output_path: &Path) -> PathBuf {
+    let mut path = output_path.as_os_str().to_owned();
+    path.push(".part");
+
+    PathBuf::from(path)
+}
+
+/// Downloads videos from the Vimeo player without any external programs.
+///
+/// The best progressive, DASH and HLS renditions are ranked by their resolution and bitrate, and
+/// downloaded in that order until one succeeds. Progressive renditions are single files with both
+/// video and audio, while the video streams of DASH and HLS renditions are combined with their
+/// audio streams if both are fragmented MP4. Renditions with an audio stream that can't be
+/// combined, like MPEG-TS, are skipped rather than downloaded without audio.
+#[derive(Debug)]
+pub struct VimeoDownloader {
+    http: reqwest::Client,
+    player_url: String,
+}
+
+impl VimeoDownloader {
+    /// Creates a downloader for the player at `player_url`, e.g. `https://player.vimeo.com`.
+    pub fn new(player_url: &str) -> Result<VimeoDownloader, Error> {
+        let http = reqwest::Client::builder()
+            .build()
+            .map_err(ErrorKind::HttpClientFailed)?;
+
+        Ok(VimeoDownloader {
+            http,
+            player_url: player_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Sends a GET request for `url` with the offstream referer, returning the response if it's
+    /// successful.
+    async fn get(&self, url: &str) -> Result<reqwest::Response, Error> {
+        trace!(url, "Requesting");
+
+        let response = self
+            .http
+            .get(url)
+            .header(header::REFERER, REFERER)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response)
+    }
+
+    async fn get_bytes(&self, url: &Url) -> Result<Vec<u8>, Error> {
+        Ok(self.get(url.as_str()).await?.bytes().await?.to_vec())
+    }
+
+    async fn get_text(&self, url: &Url) -> Result<String, Error> {
+        Ok(self.get(url.as_str()).await?.text().await?)
+    }
+
+    /// Returns the player config of the video with the given `vimeo_id`.
+    #[instrument(err, skip(self))]
+    async fn player_config(&self, vimeo_id: &str) -> Result<PlayerConfig, Error> {
+        let url = format!("{}/video/{}/config", self.player_url, vimeo_id);
+        let text = self.get(&url).await?.text().await?;
+        let jd = &mut serde_json::Deserializer::from_str(&text);
+
+        serde_path_to_error::deserialize(jd)
+            .map_err(|err| Error::from(ErrorKind::JsonDeserializationFailed(err)))
+    }
+
+    /// Downloads the file at `url` to `path`.
+    async fn download_file(
+        &self,
+        url: &str,
+        path: &Path,
+        progress: &DownloadProgress,
+    ) -> Result<(), Error> {
+        let mut response = self.get(url).await?;
+        let mut file = File::create(path).await?;
+        let total_size = response.content_length();
+        let mut downloaded = 0;
+
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+
+            if let Some(total_size) = total_size.filter(|size| *size > 0) {
+                progress.update(&Progress {
+                    percent: downloaded as f64 * 100.0 / total_size as f64,
+                    total_size: Some(HumanBytes(total_size).to_string()),
+                    ..Default::default()
+                });
+            }
+        }
+
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Returns the best rendition of the DASH manifest at `url`.
+    async fn dash_rendition(&self, url: &str) -> Result<Rendition, Error> {
+        let mut url = Url::parse(url).map_err(vimeo_error)?;
+
+        // Without this the init segments have to be requested separately
+        if !url.query_pairs().any(|(key, _)| key == "base64_init") {
+            url.query_pairs_mut().append_pair("base64_init", "1");
+        }
+
+        dash_rendition(&url, &self.get_text(&url).await?)
+    }
+
+    /// Returns the segments of the HLS media playlist at `url`.
+    async fn hls_track(&self, url: &Url) -> Result<Track, Error> {
+        parse_hls_media(url, &self.get_text(url).await?)
+    }
+
+    /// Returns the best rendition of the HLS master playlist at `url`.
+    async fn hls_rendition(&self, url: &str) -> Result<Rendition, Error> {
+        let url = Url::parse(url).map_err(vimeo_error)?;
+        let text = self.get_text(&url).await?;
+        let (variants, audio_groups) = parse_hls_master(&url, &text)?;
+
+        // A playlist without variants is a media playlist itself
+        let (variant, audio_url) = match select_hls_variant(variants, &audio_groups)? {
+            Some(selected) => selected,
+            None => {
+                return Ok(Rendition {
+                    protocol: "HLS",
+                    height: 0,
+                    bitrate: 0,
+                    source: RenditionSource::Tracks {
+                        video: parse_hls_media(&url, &text)?,
+                        audio: None,
+                    },
+                })
+            }
+        };
+
+        debug!(
+            height = variant.height,
+            bandwidth = variant.bandwidth,
+            "Selected HLS variant"
+        );
+
+        let audio = match audio_url {
+            Some(audio_url) => Some(self.hls_track(&audio_url).await?),
+            None => None,
+        };
+
+        Ok(Rendition {
+            protocol: "HLS",
+            height: variant.height,
+            bitrate: variant.bandwidth,
+            source: RenditionSource::Tracks {
+                video: self.hls_track(&variant.url).await?,
+                audio,
+            },
+        })
+    }
+
+    /// Returns the best rendition of each protocol in `files`, ranked by their resolution and
+    /// bitrate.
+    ///
+    /// A manifest that can't be read only rules out its own rendition.
+    async fn renditions(&self, files: PlayerFiles) -> Result<Vec<Rendition>, Error> {
+        let mut renditions = vec![];
+        let mut last_error = None;
+
+        if let Some(file) = files
+            .progressive
+            .into_iter()
+            .max_by_key(|file| (file.height, file.width))
+        {
+            renditions.push(Rendition {
+                protocol: "progressive",
+                height: file.height,
+                bitrate: 0,
+                source: RenditionSource::File(file.url),
+            });
+        }
+
+        if let Some(url) = files.dash.as_ref().and_then(StreamingFiles::url) {
+            match self.dash_rendition(url).await {
+                Ok(rendition) => renditions.push(rendition),
+                Err(err) => {
+                    warn!(url, "Could not read the DASH manifest: {:?}", err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        if let Some(url) = files.hls.as_ref().and_then(StreamingFiles::url) {
+            match self.hls_rendition(url).await {
+                Ok(rendition) => renditions.push(rendition),
+                Err(err) => {
+                    warn!(url, "Could not read the HLS playlist: {:?}", err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        if renditions.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                vimeo_error("the player config has no downloadable renditions")
+            }));
+        }
+
+        renditions.sort_by_key(|rendition| Reverse((rendition.height, rendition.bitrate)));
+
+        Ok(renditions)
+    }
+
+    async fn init_segment(&self, init: Option<InitSegment>) -> Result<Option<Vec<u8>>, Error> {
+        match init {
+            Some(InitSegment::Data(data)) => Ok(Some(data)),
+            Some(InitSegment::Url(url)) => Ok(Some(self.get_bytes(&url).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Downloads the segments of `video`, and of `audio` if given, to `path`.
+    ///
+    /// Returns an error if `audio` is given but either stream isn't fragmented MP4, since the
+    /// streams can't be combined then.
+    async fn download_tracks(
+        &self,
+        video: Track,
+        audio: Option<Track>,
+        path: &Path,
+        progress: &DownloadProgress,
+    ) -> Result<(), Error> {
+        let report = |index: usize, count: usize| {
+            progress.update(&Progress {
+                percent: (index + 1) as f64 * 100.0 / count as f64,
+                fragment: Some((index as u64 + 1, count as u64)),
+                ..Default::default()
+            })
+        };
+
+        let video_segments = video.segments;
+        let video_init = self.init_segment(video.init).await?;
+        let audio = match audio {
+            Some(audio) => Some((self.init_segment(audio.init).await?, audio.segments)),
+            None => None,
+        };
+
+        debug!(
+            num_video_segments = video_segments.len(),
+            num_audio_segments = ?audio.as_ref().map(|(_, segments)| segments.len()),
+            "Downloading segments"
+        );
+
+        let mut file = match (video_init, audio) {
+            (Some(video_init), Some((Some(audio_init), audio_segments))) => {
+                let (init, audio_track_id) = mp4::merge_init_segments(&video_init, &audio_init)?;
+                let mut writer = FragmentWriter::default();
+                let num_segments = video_segments.len().max(audio_segments.len());
+
+                let mut file = File::create(path).await?;
+                file.write_all(&init).await?;
+
+                // Segments are interleaved so that players don't have to read the whole file
+                // to find the audio
+                let mut segments = stream::iter(0..num_segments)
+                    .map(|index| {
+                        let video_url = video_segments.get(index).cloned();
+                        let audio_url = audio_segments.get(index).cloned();
+
+                        async move {
+                            let video = match video_url {
+                                Some(url) => Some(self.get_bytes(&url).await?),
+                                None => None,
+                            };
+                            let audio = match audio_url {
+                                Some(url) => Some(self.get_bytes(&url).await?),
+                                None => None,
+                            };
+
+                            Ok::<_, Error>((video, audio))
+                        }
+                    })
+                    .buffered(SEGMENT_CONCURRENCY);
+
+                for index in 0..num_segments {
+                    let (video, audio) = match segments.try_next().await? {
+                        Some(segment) => segment,
+                        None => break,
+                    };
+
+                    if let Some(video) = video {
+                        file.write_all(&writer.rewrite(&video, None)?).await?;
+                    }
+
+                    if let Some(audio) = audio {
+                        file.write_all(&writer.rewrite(&audio, Some(audio_track_id))?)
+                            .await?;
+                    }
+
+                    report(index, num_segments);
+                }
+
+                file
+            }
+            // The video stream has audio of its own, if any
+            (video_init, None) => {
+                let mut file = File::create(path).await?;
+
+                if let Some(video_init) = video_init {
+                    file.write_all(&video_init).await?;
+                }
+
+                let num_segments = video_segments.len();
+                let mut segments = stream::iter(video_segments)
+                    .map(|url| async move { self.get_bytes(&url).await })
+                    .buffered(SEGMENT_CONCURRENCY)
+                    .enumerate();
+
+                while let Some((index, segment)) = segments.next().await {
+                    file.write_all(&segment?).await?;
+
+                    report(index, num_segments);
+                }
+
+                file
+            }
+            // Only fragmented MP4 streams have init segments, and only they can be combined
+            _ => {
+                return Err(vimeo_error(
+                    "the audio stream can't be combined with the video stream",
+                ))
+            }
+        };
+
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Downloads the first of `renditions` that can be downloaded to `path`.
+    async fn download_renditions(
+        &self,
+        renditions: Vec<Rendition>,
+        path: &Path,
+        progress: &DownloadProgress,
+    ) -> Result<(), Error> {
+        let mut last_error = None;
+
+        for rendition in renditions {
+            debug!(
+                protocol = rendition.protocol,
+                height = rendition.height,
+                bitrate = rendition.bitrate,
+                "Downloading rendition"
+            );
+
+            let res = match rendition.source {
+                RenditionSource::File(url) => self.download_file(&url, path, progress).await,
+                RenditionSource::Tracks { video, audio } => {
+                    self.download_tracks(video, audio, path, progress).await
+                }
+            };
+
+            match res {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!(
+                        protocol = rendition.protocol,
+                        "Could not download rendition: {:?}", err
+                    );
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| vimeo_error("there are no renditions to download")))
+    }
+
+    /// Downloads the video with the given `vimeo_id` to `output_path`.
+    ///
+    /// The video is written to a `.part` file next to `output_path` until it's finished, which is
+    /// removed if the download fails.
+    #[instrument(err, skip(self, progress))]
+    async fn download_video(
+        &self,
+        vimeo_id: &str,
+        output_path: &Path,
+        progress: &DownloadProgress,
+    ) -> Result<(), Error> {
+        let files = self.player_config(vimeo_id).await?.request.files;
+        let renditions = self.renditions(files).await?;
+        let part_path = part_path(output_path);
+
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let res = match self
+            .download_renditions(renditions, &part_path, progress)
+            .await
+        {
+            Ok(()) => fs::rename(&part_path, output_path)
+                .await
+                .map_err(Error::from),
+            Err(err) => Err(err),
+        };
+
+        // Downloads are started over rather than resumed, so a partial download is of no use
+        if res.is_err() {
+            if let Err(err) = fs::remove_file(&part_path).await {
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!(?part_path, "Could not remove the partial download: {}", err);
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl Downloader for VimeoDownloader {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn download<'a>(
+        &'a self,
+        vimeo_id: &'a str,
+        output_path: &'a Path,
+        progress: &'a DownloadProgress,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        self.download_video(vimeo_id, output_path, progress).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::database::FailureCategory;
+    use crate::downloader;
+    use crate::mp4::tests::{init_segment, media_segment};
+    use crate::progress::ProgressBars;
+
+    /// Binds a local stand-in for the Vimeo player and CDNs, returning it with its base url.
+    async fn bind() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        (listener, base_url)
+    }
+
+    /// Serves the bodies in `routes`, keyed by path without the query, on `listener`.
+    ///
+    /// Like the player, requests without the offstream referer are forbidden. Other paths are
+    /// not found.
+    fn serve(listener: TcpListener, routes: HashMap<String, Vec<u8>>) {
+        let routes = Arc::new(routes);
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let routes = routes.clone();
+
+                tokio::spawn(async move {
+                    let mut request = vec![];
+                    let mut buf = [0; 1024];
+
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(len) => request.extend_from_slice(&buf[..len]),
+                        }
+                    }
+
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request
+                        .split_whitespace()
+                        .nth(1)
+                        .and_then(|target| target.split('?').next())
+                        .unwrap_or_default();
+                    let referer = format!("referer: {}", REFERER);
+                    let (status, body) =
+                        if !request.lines().any(|line| line.to_lowercase() == referer) {
+                            ("403 Forbidden", vec![])
+                        } else if let Some(body) = routes.get(path) {
+                            ("200 OK", body.clone())
+                        } else {
+                            ("404 Not Found", vec![])
+                        };
+                    let head = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+
+                    let _ = socket.write_all(head.as_bytes()).await;
+                    let _ = socket.write_all(&body).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+    }
+
+    /// Adds the player config of the video with id 1 to `routes`.
+    fn add_config(
+        routes: &mut HashMap<String, Vec<u8>>,
+        progressive: Value,
+        dash_url: Option<String>,
+    ) {
+        let dash = dash_url
+            .map(|url| json!({ "default_cdn": "fastly", "cdns": { "fastly": { "url": url } } }));
+        let config =
+            json!({ "request": { "files": { "progressive": progressive, "dash": dash } } });
+
+        routes.insert(
+            "/video/1/config".to_string(),
+            config.to_string().into_bytes(),
+        );
+    }
+
+    /// Adds a 720p progressive file to `routes`, returning its entry in the player config.
+    fn add_progressive(routes: &mut HashMap<String, Vec<u8>>, base_url: &str) -> Value {
+        routes.insert("/prog/720.mp4".to_string(), b"progressive 720p".to_vec());
+
+        json!([{ "url": format!("{}/prog/720.mp4", base_url), "width": 1280, "height": 720 }])
+    }
+
+    /// The media segments of the 1080p DASH video stream and the DASH audio stream, in pairs.
+    fn dash_segments() -> Vec<(Vec<u8>, Vec<u8>)> {
+        (1..=2)
+            .map(|n| {
+                (
+                    media_segment(n, 1, format!("video {}", n).as_bytes()),
+                    media_segment(n, 1, format!("audio {}", n).as_bytes()),
+                )
+            })
+            .collect()
+    }
+
+    /// Adds a DASH manifest with a 360p and a 1080p video stream and an audio stream to `routes`,
+    /// leaving out the segments named in `missing` and the audio init segment unless
+    /// `audio_init` is set. Returns the url of the manifest.
+    fn add_dash(
+        routes: &mut HashMap<String, Vec<u8>>,
+        base_url: &str,
+        audio_init: bool,
+        missing: &[&str],
+    ) -> String {
+        let video_init = base64::encode(init_segment(1, b"vide"));
+        let audio_init = if audio_init {
+            Some(base64::encode(init_segment(1, b"soun")))
+        } else {
+            None
+        };
+        let segments = json!([{ "url": "s1.mp4" }, { "url": "s2.mp4" }]);
+        let manifest = json!({
+            "base_url": "../",
+            "video": [
+                {
+                    "base_url": "v360/",
+                    "height": 360,
+                    "bitrate": 800_000,
+                    "init_segment": video_init,
+                    "segments": segments,
+                },
+                {
+                    "base_url": "v1080/",
+                    "height": 1080,
+                    "bitrate": 5_000_000,
+                    "init_segment": video_init,
+                    "segments": segments,
+                },
+            ],
+            "audio": [
+                {
+                    "base_url": "a128/",
+                    "bitrate": 128_000,
+                    "init_segment": audio_init,
+                    "segments": segments,
+                },
+            ],
+        });
+
+        routes.insert(
+            "/sep/video/master.json".to_string(),
+            manifest.to_string().into_bytes(),
+        );
+
+        for (index, (video, audio)) in dash_segments().into_iter().enumerate() {
+            routes.insert(format!("/sep/v1080/s{}.mp4", index + 1), video);
+            routes.insert(format!("/sep/a128/s{}.mp4", index + 1), audio);
+        }
+
+        for path in missing {
+            routes.remove(*path);
+        }
+
+        format!("{}/sep/video/master.json", base_url)
+    }
+
+    /// Returns what the DASH rendition added by [`add_dash`] is downloaded as.
+    fn merged_dash_rendition() -> Vec<u8> {
+        let (mut out, audio_track_id) =
+            mp4::merge_init_segments(&init_segment(1, b"vide"), &init_segment(1, b"soun")).unwrap();
+        let mut writer = FragmentWriter::default();
+
+        for (video, audio) in dash_segments() {
+            out.extend(writer.rewrite(&video, None).unwrap());
+            out.extend(writer.rewrite(&audio, Some(audio_track_id)).unwrap());
+        }
+
+        out
+    }
+
+    /// Returns a path to download to in a new directory named after `test`.
+    fn output_path(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("offstream-vimeo-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        dir.join("film.mp4")
+    }
+
+    /// Downloads the video with id 1 from the player at `base_url` to `path`.
+    async fn download(base_url: &str, path: &Path) -> Result<(), Error> {
+        let bars = ProgressBars::new();
+        let progress = bars.add("film.mp4");
+
+        VimeoDownloader::new(base_url)?
+            .download("1", path, &progress)
+            .await
+    }
+
+    #[test]
+    fn parses_player_config() {
+        let config: PlayerConfig = serde_json::from_value(json!({
+            "request": {
+                "files": {
+                    "progressive": [
+                        { "url": "https://cdn.example/360.mp4", "width": 640, "height": 360 },
+                    ],
+                    "dash": {
+                        "default_cdn": "fastly",
+                        "cdns": {
+                            "akamai": { "url": "https://akamai.example/master.json" },
+                            "fastly": { "url": "https://fastly.example/master.json" },
+                        },
+                    },
+                    "hls": {
+                        "cdns": { "akamai": { "url": "https://akamai.example/master.m3u8" } },
+                    },
+                },
+            },
+        }))
+        .unwrap();
+        let files = config.request.files;
+
+        assert_eq!(files.progressive.len(), 1);
+        assert_eq!(files.progressive[0].height, 360);
+        assert_eq!(
+            files.dash.as_ref().and_then(StreamingFiles::url),
+            Some("https://fastly.example/master.json")
+        );
+
+        // Without a default CDN any CDN is used
+        assert_eq!(
+            files.hls.as_ref().and_then(StreamingFiles::url),
+            Some("https://akamai.example/master.m3u8")
+        );
+    }
+
+    #[test]
+    fn parses_dash_manifest() {
+        let url = Url::parse("https://cdn.example/1/sep/video/master.json?base64_init=1").unwrap();
+        let manifest = json!({
+            "base_url": "../",
+            "video": [
+                { "base_url": "v720/", "height": 720, "bitrate": 9_000_000, "segments": [] },
+                { "base_url": "v1080a/", "height": 1080, "bitrate": 4_000_000, "segments": [] },
+                {
+                    "base_url": "v1080b/",
+                    "height": 1080,
+                    "bitrate": 5_000_000,
+                    "init_segment": base64::encode(b"init"),
+                    "segments": [{ "url": "s1.mp4" }, { "url": "s2.mp4" }],
+                },
+            ],
+            "audio": [
+                { "base_url": "a64/", "bitrate": 64_000, "segments": [] },
+                { "base_url": "a128/", "bitrate": 128_000, "segments": [{ "url": "s1.mp4" }] },
+            ],
+        });
+
+        let rendition = dash_rendition(&url, &manifest.to_string()).unwrap();
+
+        assert_eq!((rendition.height, rendition.bitrate), (1080, 5_000_000));
+
+        let (video, audio) = match rendition.source {
+            RenditionSource::Tracks { video, audio } => (video, audio.unwrap()),
+            source => panic!("unexpected source {:?}", source),
+        };
+
+        assert!(matches!(video.init, Some(InitSegment::Data(data)) if data == b"init"));
+        assert_eq!(
+            video.segments,
+            [
+                Url::parse("https://cdn.example/1/sep/v1080b/s1.mp4").unwrap(),
+                Url::parse("https://cdn.example/1/sep/v1080b/s2.mp4").unwrap(),
+            ]
+        );
+        assert!(audio.init.is_none());
+        assert_eq!(
+            audio.segments,
+            [Url::parse("https://cdn.example/1/sep/a128/s1.mp4").unwrap()]
+        );
+    }
+
+    #[test]
+    fn rejects_dash_manifest_without_video() {
+        let url = Url::parse("https://cdn.example/master.json").unwrap();
+
+        let err = dash_rendition(&url, r#"{ "video": [], "audio": [] }"#).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            ErrorKind::VimeoError("the DASH manifest has no video streams".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn parses_hls_playlists() {
+        let url = Url::parse("https://cdn.example/1/master.m3u8").unwrap();
+        let master = r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="audio-high",NAME="Audio",URI="audio/high.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=900000,RESOLUTION=640x360,CODECS="avc1.4d401e,mp4a.40.2",AUDIO="audio-high"
+video/360.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,AUDIO="audio-high"
+
+video/1080.m3u8
+"#;
+
+        let (variants, audio_groups) = parse_hls_master(&url, master).unwrap();
+
+        let variants: Vec<_> = variants
+            .iter()
+            .map(|variant| {
+                (
+                    variant.height,
+                    variant.bandwidth,
+                    variant.audio_group.as_deref(),
+                    variant.url.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            variants,
+            [
+                (
+                    360,
+                    900_000,
+                    Some("audio-high"),
+                    "https://cdn.example/1/video/360.m3u8"
+                ),
+                (
+                    1080,
+                    5_000_000,
+                    Some("audio-high"),
+                    "https://cdn.example/1/video/1080.m3u8"
+                ),
+            ]
+        );
+        assert_eq!(
+            audio_groups["audio-high"].as_str(),
+            "https://cdn.example/1/audio/high.m3u8"
+        );
+
+        let media_url = Url::parse("https://cdn.example/1/video/1080.m3u8").unwrap();
+        let media = r#"#EXTM3U
+#EXT-X-MAP:URI="init.mp4"
+#EXTINF:6.0,
+s1.m4s
+#EXTINF:6.0,
+s2.m4s
+#EXT-X-ENDLIST
+"#;
+
+        let track = parse_hls_media(&media_url, media).unwrap();
+
+        assert!(matches!(
+            track.init,
+            Some(InitSegment::Url(url)) if url.as_str() == "https://cdn.example/1/video/init.mp4"
+        ));
+        assert_eq!(
+            track.segments,
+            [
+                Url::parse("https://cdn.example/1/video/s1.m4s").unwrap(),
+                Url::parse("https://cdn.example/1/video/s2.m4s").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_hls_variants_without_audio() {
+        let url = Url::parse("https://cdn.example/1/master.m3u8").unwrap();
+        let master = r#"#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID="audio-low",NAME="Audio",URI="audio/low.m3u8"
+#EXT-X-STREAM-INF:BANDWIDTH=900000,RESOLUTION=640x360,AUDIO="audio-low"
+video/360.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,AUDIO="audio-high"
+video/1080.m3u8
+"#;
+
+        let (variants, audio_groups) = parse_hls_master(&url, master).unwrap();
+        let (variant, audio_url) = select_hls_variant(variants, &audio_groups)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(variant.url.as_str(), "https://cdn.example/1/video/360.m3u8");
+        assert_eq!(
+            audio_url.unwrap().as_str(),
+            "https://cdn.example/1/audio/low.m3u8"
+        );
+
+        let master = r#"#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,AUDIO="audio-high"
+video/1080.m3u8
+"#;
+
+        let (variants, audio_groups) = parse_hls_master(&url, master).unwrap();
+        let err = select_hls_variant(variants, &audio_groups).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            ErrorKind::VimeoError(
+                "the audio stream can't be combined with the video stream".to_string()
+            )
+            .to_string()
+        );
+        assert!(select_hls_variant(vec![], &audio_groups).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn downloads_progressive_rendition() {
+        let (listener, base_url) = bind().await;
+        let mut routes = HashMap::new();
+        let progressive = add_progressive(&mut routes, &base_url);
+        add_config(&mut routes, progressive, None);
+        serve(listener, routes);
+
+        let path = output_path("progressive");
+        download(&base_url, &path).await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"progressive 720p");
+        assert!(!part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn downloads_dash_rendition_with_higher_resolution() {
+        let (listener, base_url) = bind().await;
+        let mut routes = HashMap::new();
+        let progressive = add_progressive(&mut routes, &base_url);
+        let dash_url = add_dash(&mut routes, &base_url, true, &[]);
+        add_config(&mut routes, progressive, Some(dash_url));
+        serve(listener, routes);
+
+        let path = output_path("dash");
+        download(&base_url, &path).await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), merged_dash_rendition());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_audio_cant_be_combined() {
+        let (listener, base_url) = bind().await;
+        let mut routes = HashMap::new();
+        let progressive = add_progressive(&mut routes, &base_url);
+        let dash_url = add_dash(&mut routes, &base_url, false, &[]);
+        add_config(&mut routes, progressive, Some(dash_url));
+        serve(listener, routes);
+
+        let path = output_path("fallback");
+        download(&base_url, &path).await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"progressive 720p");
+    }
+
+    #[tokio::test]
+    async fn fails_when_audio_cant_be_combined() {
+        let (listener, base_url) = bind().await;
+        let mut routes = HashMap::new();
+        let dash_url = add_dash(&mut routes, &base_url, false, &[]);
+        add_config(&mut routes, json!([]), Some(dash_url));
+        serve(listener, routes);
+
+        let path = output_path("no-audio");
+        let err = download(&base_url, &path).await.unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            ErrorKind::VimeoError(
+                "the audio stream can't be combined with the video stream".to_string()
+            )
+            .to_string()
+        );
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn removes_partial_download_on_failure() {
+        let (listener, base_url) = bind().await;
+        let mut routes = HashMap::new();
+        let dash_url = add_dash(&mut routes, &base_url, true, &["/sep/v1080/s2.mp4"]);
+        add_config(&mut routes, json!([]), Some(dash_url));
+        serve(listener, routes);
+
+        let path = output_path("partial");
+        let err = download(&base_url, &path).await.unwrap_err();
+
+        assert_eq!(
+            downloader::attempt_failure(&err).category,
+            FailureCategory::Unavailable
+        );
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+    }
+}